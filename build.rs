@@ -2,4 +2,15 @@
 fn main() {
     // trigger recompilation when a new migration is added
     println!("cargo:rerun-if-changed=migrations");
+
+    // the stored difficulty attributes are versioned by the live calculator's revision
+    println!("cargo:rerun-if-changed=Cargo.toml");
+    let manifest = std::fs::read_to_string("Cargo.toml").expect("failed to read Cargo.toml");
+    let rev = manifest
+        .lines()
+        .find(|line| line.starts_with("akatsuki-pp-rs "))
+        .and_then(|line| line.split("rev = \"").nth(1))
+        .and_then(|rest| rest.split('"').next())
+        .expect("Cargo.toml has no akatsuki-pp-rs git revision");
+    println!("cargo:rustc-env=AKATSUKI_PP_REV={}", &rev[..7]);
 }
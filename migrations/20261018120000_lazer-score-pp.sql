@@ -0,0 +1,6 @@
+create table lazer_score_pp (
+    score_id bigint not null,
+    rx int not null,
+    pp float not null,
+    primary key (score_id, rx)
+);
@@ -9,8 +9,13 @@ use crate::context::Context;
 mod error;
 mod routes;
 
+// the live calculation, for callers outside of the API such as the offline calculator
+pub use routes::calculate::{calculate_score, CalculateResponse, DifficultyCache, ScoreState};
+
 fn api_router() -> Router {
     routes::calculate::router()
+        .merge(routes::scores::router())
+        .merge(routes::beatmaps::router())
         .merge(routes::reworks::queue::router())
         .merge(routes::reworks::scores::router())
         .merge(routes::reworks::reworks::router())
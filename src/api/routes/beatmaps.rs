@@ -0,0 +1,281 @@
+use std::sync::Arc;
+
+use akatsuki_pp_rs::{
+    any::{Difficulty, Strains},
+    model::beatmap::ConvertStatus,
+};
+use axum::{
+    async_trait,
+    extract::{Extension, FromRequest, Path, Query, RequestParts},
+    response::IntoResponse,
+    routing::{delete, get},
+    Json, Router,
+};
+use reqwest::StatusCode;
+
+use crate::{
+    api::error::AppResult,
+    context::Context,
+    models::{mode::Mode, mods, privileges},
+    usecases::{self, beatmaps::IncompatibleConvert, sessions::SessionAccess},
+};
+
+use super::calculate::fetch_beatmap;
+
+pub fn router() -> Router {
+    Router::new()
+        .route(
+            "/api/v1/beatmaps/:beatmap_id/strains",
+            get(get_beatmap_strains),
+        )
+        .route("/api/v1/beatmaps/cache", get(get_beatmap_cache_stats))
+        .route(
+            "/api/v1/beatmaps/:beatmap_id/cache",
+            delete(invalidate_cached_beatmap),
+        )
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct StrainsQuery {
+    pub mode: i32,
+    #[serde(default, deserialize_with = "mods::deserialize")]
+    pub mods: i32,
+    pub lazer: Option<bool>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct StrainSeries {
+    pub skill: &'static str,
+    pub values: Vec<f64>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct StrainsResponse {
+    pub beatmap_id: i32,
+    pub mode: i32,
+    /// The mods the strains were calculated with, after normalisation.
+    pub mods: i32,
+    pub mod_changes: Vec<String>,
+    pub is_convert: bool,
+    /// Length of each strain section in milliseconds of map time.
+    pub section_length: f64,
+    pub strains: Vec<StrainSeries>,
+}
+
+fn strain_series(strains: Strains) -> Vec<StrainSeries> {
+    match strains {
+        Strains::Osu(strains) => vec![
+            StrainSeries {
+                skill: "aim",
+                values: strains.aim,
+            },
+            StrainSeries {
+                skill: "aim_no_sliders",
+                values: strains.aim_no_sliders,
+            },
+            StrainSeries {
+                skill: "speed",
+                values: strains.speed,
+            },
+            StrainSeries {
+                skill: "flashlight",
+                values: strains.flashlight,
+            },
+        ],
+        Strains::Taiko(strains) => vec![
+            StrainSeries {
+                skill: "color",
+                values: strains.color,
+            },
+            StrainSeries {
+                skill: "rhythm",
+                values: strains.rhythm,
+            },
+            StrainSeries {
+                skill: "stamina",
+                values: strains.stamina,
+            },
+        ],
+        Strains::Catch(strains) => vec![StrainSeries {
+            skill: "movement",
+            values: strains.movement,
+        }],
+        Strains::Mania(strains) => vec![StrainSeries {
+            skill: "strain",
+            values: strains.strains,
+        }],
+    }
+}
+
+async fn get_beatmap_strains(
+    Extension(ctx): Extension<Arc<Context>>,
+    Path(beatmap_id): Path<i32>,
+    Query(query): Query<StrainsQuery>,
+) -> AppResult<impl IntoResponse> {
+    let mode = match Mode::try_from(query.mode) {
+        Ok(mode) => mode,
+        Err(e) => return Ok((StatusCode::BAD_REQUEST, e.to_string()).into_response()),
+    };
+
+    let normalised = match mods::normalise(query.mods as u32, mode) {
+        Ok(normalised) => normalised,
+        Err(e) => return Ok((StatusCode::BAD_REQUEST, e.to_string()).into_response()),
+    };
+
+    // the 2019 relax calculator has strain skills of its own, which it does not expose
+    if mode == Mode::Std && normalised.mods & mods::RELAX > 0 {
+        return Ok((
+            StatusCode::BAD_REQUEST,
+            "strain timelines are not available for relax",
+        )
+            .into_response());
+    }
+
+    let mut beatmap = match fetch_beatmap(beatmap_id, ctx.clone()).await {
+        Ok(beatmap) => beatmap.beatmap.clone(),
+        Err(e) => return Ok((e.kind.status_code(), Json(e)).into_response()),
+    };
+
+    let is_convert = match beatmap.convert_in_place(mode.game_mode()) {
+        ConvertStatus::Noop => false,
+        ConvertStatus::Conversion => true,
+        ConvertStatus::Incompatible => {
+            let e = IncompatibleConvert {
+                beatmap_mode: Mode::from(beatmap.mode),
+                mode,
+            };
+            return Ok((StatusCode::BAD_REQUEST, e.to_string()).into_response());
+        }
+    };
+
+    let strains = Difficulty::new()
+        .mods(normalised.mods)
+        .lazer(query.lazer.unwrap_or(false))
+        .strains(&beatmap);
+
+    let section_length = strains.section_len();
+    let strains = strain_series(strains);
+
+    log::info!(
+        beatmap_id = beatmap_id,
+        mode = query.mode,
+        mods = normalised.mods,
+        sections = strains.first().map_or(0, |series| series.values.len());
+        "Calculated strain timeline.",
+    );
+
+    Ok(Json(StrainsResponse {
+        beatmap_id,
+        mode: query.mode,
+        mods: normalised.mods as i32,
+        mod_changes: normalised.changes,
+        is_convert,
+        section_length,
+        strains,
+    })
+    .into_response())
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SessionQuery {
+    session: String,
+}
+
+/// The `session` query parameter, the same rework session token the queue takes. Requests
+/// without one are turned away before anything else about them is looked at.
+struct SessionToken(String);
+
+#[async_trait]
+impl<B: Send> FromRequest<B> for SessionToken {
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        match Query::<SessionQuery>::from_request(req).await {
+            Ok(Query(query)) => Ok(Self(query.session)),
+            Err(_) => Err((StatusCode::UNAUTHORIZED, "a session is required")),
+        }
+    }
+}
+
+/// Why the session cannot manage beatmaps, if it cannot.
+async fn reject_non_admin(
+    session: &str,
+    ctx: &Context,
+) -> anyhow::Result<Option<(StatusCode, &'static str)>> {
+    let access =
+        usecases::sessions::authorize(session, privileges::ADMIN_MANAGE_BEATMAPS, ctx).await?;
+
+    Ok(match access {
+        SessionAccess::Granted { .. } => None,
+        SessionAccess::InvalidSession => Some((StatusCode::UNAUTHORIZED, "Invalid session token")),
+        SessionAccess::Denied(message) => Some((StatusCode::FORBIDDEN, message)),
+    })
+}
+
+async fn get_beatmap_cache_stats(
+    SessionToken(session): SessionToken,
+    Extension(ctx): Extension<Arc<Context>>,
+) -> AppResult<impl IntoResponse> {
+    if let Some(rejection) = reject_non_admin(&session, &ctx).await? {
+        return Ok(rejection.into_response());
+    }
+
+    Ok(Json(ctx.beatmap_cache.stats()).into_response())
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct InvalidatedBeatmap {
+    pub beatmap_id: i32,
+    pub invalidated: usize,
+}
+
+async fn invalidate_cached_beatmap(
+    SessionToken(session): SessionToken,
+    Extension(ctx): Extension<Arc<Context>>,
+    Path(beatmap_id): Path<i32>,
+) -> AppResult<impl IntoResponse> {
+    if let Some(rejection) = reject_non_admin(&session, &ctx).await? {
+        return Ok(rejection.into_response());
+    }
+
+    let invalidated = ctx.beatmap_cache.invalidate_beatmap(beatmap_id);
+
+    log::info!(
+        beatmap_id = beatmap_id,
+        invalidated = invalidated;
+        "Invalidated cached beatmap.",
+    );
+
+    Ok(Json(InvalidatedBeatmap {
+        beatmap_id,
+        invalidated,
+    })
+    .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::{Method, Request};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn cache_routes_reject_requests_without_a_session() {
+        for (method, uri) in [
+            (Method::DELETE, "/api/v1/beatmaps/75/cache"),
+            (Method::GET, "/api/v1/beatmaps/cache"),
+        ] {
+            let request = Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap();
+
+            // no context is attached either, so getting past the session check would fail
+            // with a 500 instead
+            let response = router().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+    }
+}
@@ -1,38 +1,477 @@
+use crate::models::beatmap_cache::CachedBeatmap;
+use crate::models::difficulty_attributes::{
+    DifficultyAttributesKey, StoredDifficultyAttributes, LIVE_CALCULATOR_VERSION,
+};
+use crate::models::{mode::Mode, mods, replay::ReplayHeader};
 use crate::usecases;
+use crate::usecases::beatmaps::{
+    BeatmapMd5Mismatch, BeatmapSourceUnavailable, IncompatibleConvert, UnparseableBeatmap,
+};
 use crate::{api::error::AppResult, context::Context};
-use akatsuki_pp_rs::model::mode::GameMode;
-use akatsuki_pp_rs::{any::PerformanceAttributes, Beatmap};
+use akatsuki_pp_rs::model::hit_object::HitObjectKind;
+use akatsuki_pp_rs::osu::OsuDifficultyAttributes;
+use akatsuki_pp_rs::{
+    any::{DifficultyAttributes, Performance, PerformanceAttributes},
+    Beatmap,
+};
 use anyhow::anyhow;
-use axum::response::IntoResponse;
-use axum::{extract::Extension, routing::post, Json, Router};
+use axum::body::Bytes;
+use axum::extract::{ContentLengthLimit, Path, Query};
+use axum::http::header;
+use axum::response::{Headers, IntoResponse};
+use axum::routing::{get, post};
+use axum::{extract::Extension, Json, Router};
+use futures::stream::FuturesUnordered;
+use futures::StreamExt;
 use reqwest::StatusCode;
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+const MAX_OSU_FILE_SIZE: u64 = 10 * 1024 * 1024;
+const MAX_REPLAY_FILE_SIZE: u64 = 10 * 1024 * 1024;
+const MAX_CONCURRENT_BEATMAPS: usize = 10;
+const MAX_PP_TABLE_ROWS: usize = 20;
+const DEFAULT_PP_TABLE_ACCURACIES: [f32; 5] = [95.0, 97.0, 98.0, 99.0, 100.0];
+// beatmaps can still be updated and calculators change, so don't cache for too long
+const CALCULATE_CACHE_CONTROL: &str = "public, max-age=3600";
 
 pub fn router() -> Router {
-    Router::new().route("/api/v1/calculate", post(calculate_play))
+    // axum tries the most recently added route first, so the literal paths below
+    // must be added after the beatmap id one
+    Router::new()
+        .route("/api/v1/calculate/:beatmap_id", get(calculate_beatmap))
+        .route("/api/v1/calculate", post(calculate_play))
+        .route("/api/v1/calculate/replay", post(calculate_replay))
+        .route("/api/v1/calculate/osu-file", post(calculate_osu_file))
+        .route("/api/v1/calculate/pp-table", post(calculate_pp_table))
 }
 
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct CalculateRequest {
     pub beatmap_id: i32,
     pub beatmap_md5: String,
+    #[serde(flatten)]
+    pub score: ScoreState,
+}
+
+/// The score fields of a calculation, independent of where the beatmap comes from.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct ScoreState {
     pub mode: i32,
+    #[serde(deserialize_with = "mods::deserialize")]
     pub mods: i32,
     pub max_combo: i32,
     pub accuracy: Option<f32>,
     pub count_300: Option<i32>,
     pub count_100: Option<i32>,
     pub count_50: Option<i32>,
+    // taiko, catch and mania judgements, optional alongside the counts above
+    pub count_geki: Option<i32>,
+    pub count_katu: Option<i32>,
     pub miss_count: i32,
+
+    /// Use the lazer calculation instead of stable; defaults to stable.
+    pub lazer: Option<bool>,
+    // lazer-only statistics, ignored by stable calculations
+    pub slider_end_hits: Option<i32>,
+    pub large_tick_hits: Option<i32>,
+    pub small_tick_hits: Option<i32>,
+
+    // difficulty adjustments, applied before the mods like osu!lazer's difficulty adjust
+    pub ar: Option<f32>,
+    pub od: Option<f32>,
+    pub cs: Option<f32>,
+    pub hp: Option<f32>,
+    /// Replaces the clock rate implied by DT/HT, e.g. `1.2` for a 1.2x rate edit.
+    pub clock_rate: Option<f64>,
+
+    /// Only calculate up to this many hit objects, for plays that ended partway through.
+    pub passed_objects: Option<i32>,
+
+    /// What `mods::normalise` changed about `mods`, filled in by `prepare`.
+    #[serde(skip)]
+    pub mod_changes: Vec<String>,
+}
+
+/// Everything the difficulty attributes of a beatmap depend on.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct DifficultyKey {
+    mode: i32,
+    mods: u32,
+    lazer: bool,
+    ar: Option<u32>,
+    od: Option<u32>,
+    cs: Option<u32>,
+    hp: Option<u32>,
+    clock_rate: Option<u64>,
+    passed_objects: Option<i32>,
+}
+
+impl DifficultyKey {
+    /// The key of these attributes in the attributes store, `None` when they depend on
+    /// more than the mods.
+    fn stored(&self, beatmap_md5: &str) -> Option<DifficultyAttributesKey> {
+        let adjusted = self.ar.is_some()
+            || self.od.is_some()
+            || self.cs.is_some()
+            || self.hp.is_some()
+            || self.clock_rate.is_some()
+            || self.passed_objects.is_some();
+        if adjusted {
+            return None;
+        }
+
+        let calculator = if self.mode == 0 && self.mods & RX as u32 > 0 {
+            "relax"
+        } else if self.lazer {
+            "live-lazer"
+        } else {
+            "live"
+        };
+
+        Some(DifficultyAttributesKey {
+            calculator: calculator.to_string(),
+            version: LIVE_CALCULATOR_VERSION,
+            beatmap_md5: beatmap_md5.to_string(),
+            mode: self.mode,
+            mods: self.mods,
+        })
+    }
+}
+
+impl ScoreState {
+    fn is_relax(&self) -> bool {
+        self.mods & RX > 0 && self.mode == 0
+    }
+
+    fn is_autopilot(&self) -> bool {
+        self.mods & AP > 0 && self.mode == 0
+    }
+
+    fn is_lazer(&self) -> bool {
+        self.lazer.unwrap_or(false)
+    }
+
+    fn validate(&self) -> Result<(), &'static str> {
+        if Mode::try_from(self.mode).is_err() {
+            return Err("invalid mode: must be 0 (std), 1 (taiko), 2 (catch) or 3 (mania)");
+        }
+
+        let have_hit_statistics =
+            self.count_300.is_some() && self.count_100.is_some() && self.count_50.is_some();
+        let have_accuracy = self.accuracy.is_some();
+
+        if have_accuracy == have_hit_statistics {
+            return Err("you must pass accuracy OR hit results");
+        }
+
+        let counts = [
+            (self.count_300, "count_300 must not be negative"),
+            (self.count_100, "count_100 must not be negative"),
+            (self.count_50, "count_50 must not be negative"),
+            (self.count_geki, "count_geki must not be negative"),
+            (self.count_katu, "count_katu must not be negative"),
+            (Some(self.miss_count), "miss_count must not be negative"),
+            (Some(self.max_combo), "max_combo must not be negative"),
+        ];
+        for (count, error) in counts {
+            if matches!(count, Some(count) if count < 0) {
+                return Err(error);
+            }
+        }
+
+        if self.is_relax() && self.is_lazer() {
+            return Err("lazer calculations are not supported for relax scores");
+        }
+
+        if self.is_relax() && self.is_autopilot() {
+            return Err("relax and autopilot cannot be combined");
+        }
+
+        let overrides = [self.ar, self.od, self.cs, self.hp];
+        if overrides
+            .iter()
+            .flatten()
+            .any(|value| !(0.0..=11.0).contains(value))
+        {
+            return Err("ar, od, cs and hp must be between 0 and 11");
+        }
+
+        if matches!(self.passed_objects, Some(passed_objects) if passed_objects < 1) {
+            return Err("passed_objects must be at least 1");
+        }
+
+        if let Some(clock_rate) = self.clock_rate {
+            if !(0.5..=2.0).contains(&clock_rate) {
+                return Err("clock_rate must be between 0.5 and 2.0");
+            }
+        }
+
+        Ok(())
+    }
+
+    fn normalise_mods(&mut self) -> Result<(), String> {
+        let mode = Mode::try_from(self.mode).map_err(|e| e.to_string())?;
+        let normalised = mods::normalise(self.mods as u32, mode).map_err(|e| e.to_string())?;
+
+        self.mods = normalised.mods as i32;
+        self.mod_changes = normalised.changes;
+        Ok(())
+    }
+
+    /// Validates the score and normalises its mods, ready to be calculated.
+    pub fn prepare(&mut self) -> Result<(), String> {
+        self.validate()?;
+        self.normalise_mods()
+    }
+
+    fn has_overrides(&self) -> bool {
+        self.ar.is_some() || self.od.is_some() || self.cs.is_some() || self.hp.is_some()
+    }
+
+    fn difficulty_key(&self) -> DifficultyKey {
+        DifficultyKey {
+            mode: self.mode,
+            mods: self.mods as u32,
+            lazer: self.is_lazer(),
+            ar: self.ar.map(f32::to_bits),
+            od: self.od.map(f32::to_bits),
+            cs: self.cs.map(f32::to_bits),
+            hp: self.hp.map(f32::to_bits),
+            clock_rate: self.clock_rate.map(f64::to_bits),
+            passed_objects: self.passed_objects,
+        }
+    }
+
+    /// The 2019 calculator has no override or clock rate hooks, so they are written
+    /// into a copy of the beatmap instead. A custom clock rate rescales the timeline of
+    /// the map, so that playing it at the rate of the mods plays the original at
+    /// `clock_rate`.
+    fn beatmap_with_overrides<'a>(&self, beatmap: &'a Beatmap) -> Cow<'a, Beatmap> {
+        if !self.has_overrides() && self.clock_rate.is_none() {
+            return Cow::Borrowed(beatmap);
+        }
+
+        let mut beatmap = beatmap.clone();
+        if let Some(ar) = self.ar {
+            beatmap.ar = ar;
+        }
+        if let Some(od) = self.od {
+            beatmap.od = od;
+        }
+        if let Some(cs) = self.cs {
+            beatmap.cs = cs;
+        }
+        if let Some(hp) = self.hp {
+            beatmap.hp = hp;
+        }
+
+        if let Some(clock_rate) = self.clock_rate {
+            let scale = mods::clock_rate(self.mods as u32) / clock_rate;
+
+            for hit_object in &mut beatmap.hit_objects {
+                hit_object.start_time *= scale;
+                if let HitObjectKind::Spinner(spinner) = &mut hit_object.kind {
+                    spinner.duration *= scale;
+                }
+            }
+            for timing_point in &mut beatmap.timing_points {
+                timing_point.time *= scale;
+                timing_point.beat_len *= scale;
+            }
+            for difficulty_point in &mut beatmap.difficulty_points {
+                difficulty_point.time *= scale;
+            }
+        }
+
+        Cow::Owned(beatmap)
+    }
 }
 
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct CalculateResponse {
     pub stars: f32,
     pub pp: f32,
+    /// The mods the values were calculated with, after normalisation.
+    pub mods: i32,
+    pub mod_acronyms: String,
+    pub mod_changes: Vec<String>,
     pub ar: f32,
     pub od: f32,
+    pub cs: f32,
+    pub hp: f32,
+    pub bpm: f32,
+    pub clock_rate: f32,
     pub max_combo: i32,
+    pub lazer: bool,
+    /// Whether a std beatmap was converted to the requested mode.
+    pub is_convert: bool,
+
+    pub aim_pp: Option<f32>,
+    pub speed_pp: Option<f32>,
+    pub accuracy_pp: Option<f32>,
+    pub flashlight_pp: Option<f32>,
+    // taiko & mania strain value
+    pub difficulty_pp: Option<f32>,
+
+    pub n_circles: Option<i32>,
+    pub n_sliders: Option<i32>,
+    pub n_spinners: Option<i32>,
+    pub n_fruits: Option<i32>,
+    pub n_droplets: Option<i32>,
+    pub n_tiny_droplets: Option<i32>,
+    pub n_objects: Option<i32>,
+}
+
+impl CalculateResponse {
+    fn new(stars: f32, pp: f32, beatmap: &Beatmap, score: &ScoreState) -> Self {
+        let mut map_attributes = beatmap.attributes().mods(score.mods as u32);
+        if let Some(ar) = score.ar {
+            map_attributes = map_attributes.ar(ar, false);
+        }
+        if let Some(od) = score.od {
+            map_attributes = map_attributes.od(od, false);
+        }
+        if let Some(cs) = score.cs {
+            map_attributes = map_attributes.cs(cs, false);
+        }
+        if let Some(hp) = score.hp {
+            map_attributes = map_attributes.hp(hp, false);
+        }
+        if let Some(clock_rate) = score.clock_rate {
+            map_attributes = map_attributes.clock_rate(clock_rate);
+        }
+        let map_attributes = map_attributes.build();
+
+        Self {
+            stars,
+            pp,
+            mods: score.mods,
+            mod_acronyms: mods::format(score.mods as u32),
+            mod_changes: score.mod_changes.clone(),
+            ar: map_attributes.ar as f32,
+            od: map_attributes.od as f32,
+            cs: map_attributes.cs as f32,
+            hp: map_attributes.hp as f32,
+            bpm: round((beatmap.bpm() * map_attributes.clock_rate) as f32, 2),
+            clock_rate: map_attributes.clock_rate as f32,
+            max_combo: 0,
+            lazer: false,
+            is_convert: false,
+            aim_pp: None,
+            speed_pp: None,
+            accuracy_pp: None,
+            flashlight_pp: None,
+            difficulty_pp: None,
+            n_circles: None,
+            n_sliders: None,
+            n_spinners: None,
+            n_fruits: None,
+            n_droplets: None,
+            n_tiny_droplets: None,
+            n_objects: None,
+        }
+    }
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CalculateErrorKind {
+    InvalidInput,
+    BeatmapNotFound,
+    BeatmapFetchFailed,
+    BeatmapSourceUnavailable,
+    BeatmapMd5Mismatch,
+    ParseFailure,
+    IncompatibleConvert,
+    CalculationFailed,
+}
+
+impl CalculateErrorKind {
+    pub(super) fn as_str(&self) -> &'static str {
+        match self {
+            Self::InvalidInput => "invalid_input",
+            Self::BeatmapNotFound => "beatmap_not_found",
+            Self::BeatmapFetchFailed => "beatmap_fetch_failed",
+            Self::BeatmapSourceUnavailable => "beatmap_source_unavailable",
+            Self::BeatmapMd5Mismatch => "beatmap_md5_mismatch",
+            Self::ParseFailure => "parse_failure",
+            Self::IncompatibleConvert => "incompatible_convert",
+            Self::CalculationFailed => "calculation_failed",
+        }
+    }
+
+    pub(super) fn status_code(&self) -> StatusCode {
+        match self {
+            Self::InvalidInput => StatusCode::BAD_REQUEST,
+            Self::BeatmapNotFound => StatusCode::NOT_FOUND,
+            Self::BeatmapFetchFailed => StatusCode::BAD_GATEWAY,
+            Self::BeatmapSourceUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            Self::BeatmapMd5Mismatch => StatusCode::CONFLICT,
+            Self::ParseFailure => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::IncompatibleConvert => StatusCode::BAD_REQUEST,
+            Self::CalculationFailed => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct CalculateError {
+    pub kind: CalculateErrorKind,
+    pub message: String,
+}
+
+impl CalculateError {
+    pub(super) fn new(kind: CalculateErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+        }
+    }
+
+    fn from_fetch_error(beatmap_id: i32, error: &anyhow::Error) -> Self {
+        if let Some(e) = error.downcast_ref::<UnparseableBeatmap>() {
+            return Self::new(CalculateErrorKind::ParseFailure, e.to_string());
+        }
+
+        if let Some(e) = error.downcast_ref::<BeatmapMd5Mismatch>() {
+            return Self::new(CalculateErrorKind::BeatmapMd5Mismatch, e.to_string());
+        }
+
+        if let Some(e) = error.downcast_ref::<BeatmapSourceUnavailable>() {
+            return Self::new(CalculateErrorKind::BeatmapSourceUnavailable, e.to_string());
+        }
+
+        if usecases::beatmaps::is_beatmap_missing(error) {
+            return Self::new(
+                CalculateErrorKind::BeatmapNotFound,
+                format!("beatmap {} not found", beatmap_id),
+            );
+        }
+
+        Self::new(
+            CalculateErrorKind::BeatmapFetchFailed,
+            format!("failed to fetch beatmap {}", beatmap_id),
+        )
+    }
+
+    pub(super) fn from_calculation_error(error: &anyhow::Error) -> Self {
+        match error.downcast_ref::<IncompatibleConvert>() {
+            Some(e) => Self::new(CalculateErrorKind::IncompatibleConvert, e.to_string()),
+            None => Self::new(CalculateErrorKind::CalculationFailed, error.to_string()),
+        }
+    }
+}
+
+/// One entry of a batch response, in the same position as its request item.
+/// Successful items serialize exactly like a plain `CalculateResponse`.
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+pub enum CalculateResult {
+    Ok(CalculateResponse),
+    Err { error: CalculateError },
 }
 
 fn round(x: f32, decimals: u32) -> f32 {
@@ -40,26 +479,122 @@ fn round(x: f32, decimals: u32) -> f32 {
     (x * y).round() / y
 }
 
-async fn calculate_relax_pp(
-    request: &CalculateRequest,
-    context: Arc<Context>,
-) -> anyhow::Result<CalculateResponse> {
-    let beatmap_bytes =
-        usecases::beatmaps::fetch_beatmap_osu_file(request.beatmap_id, context).await?;
-    let beatmap = Beatmap::from_bytes(&beatmap_bytes)?;
+/// Difficulty attributes of a single beatmap, calculated once per mode & mods
+/// combination and reused for every score on it. `load` and `save` share them
+/// with other calculations through the attributes store.
+#[derive(Default)]
+pub struct DifficultyCache {
+    relax: HashMap<DifficultyKey, OsuDifficultyAttributes>,
+    rosu: HashMap<DifficultyKey, DifficultyAttributes>,
+    unsaved: Vec<DifficultyKey>,
+}
+
+impl DifficultyCache {
+    fn relax(&mut self, score: &ScoreState, beatmap: &Beatmap) -> &OsuDifficultyAttributes {
+        let key = score.difficulty_key();
+        if !self.relax.contains_key(&key) {
+            let beatmap = score.beatmap_with_overrides(beatmap);
+            let mut difficulty = akatsuki_pp_rs::osu_2019::stars::stars(
+                &beatmap,
+                (score.mods as u32).into(),
+                score.passed_objects.map(|n| n as usize),
+            );
+
+            // the rescaled timeline covers the strains, but AR and OD were still worked
+            // out with the rate of the mods
+            if let Some(clock_rate) = score.clock_rate {
+                let map_attributes = beatmap
+                    .attributes()
+                    .mods(score.mods as u32)
+                    .clock_rate(clock_rate)
+                    .build();
+                difficulty.ar = map_attributes.ar;
+                difficulty.od = map_attributes.od;
+            }
+            self.relax.insert(key, difficulty);
+            self.unsaved.push(key);
+        }
+
+        &self.relax[&key]
+    }
+
+    fn rosu(
+        &mut self,
+        score: &ScoreState,
+        beatmap: &Beatmap,
+    ) -> anyhow::Result<&DifficultyAttributes> {
+        let key = score.difficulty_key();
+        if !self.rosu.contains_key(&key) {
+            let difficulty = calculate_rosu_difficulty(score, beatmap)?;
+            self.rosu.insert(key, difficulty);
+            self.unsaved.push(key);
+        }
+
+        Ok(&self.rosu[&key])
+    }
+
+    /// Loads the attributes `score` needs from the attributes store, if an earlier
+    /// calculation saved them there.
+    pub async fn load(&mut self, score: &ScoreState, beatmap_md5: &str, context: Arc<Context>) {
+        let key = score.difficulty_key();
+        if self.relax.contains_key(&key) || self.rosu.contains_key(&key) {
+            return;
+        }
 
-    let mut calculate = akatsuki_pp_rs::osu_2019::OsuPP::from_map(&beatmap)
-        .mods(request.mods as u32)
-        .combo(request.max_combo as u32);
+        let Some(stored_key) = key.stored(beatmap_md5) else {
+            return;
+        };
+
+        match usecases::difficulty_attributes::fetch(&stored_key, context).await {
+            Some(StoredDifficultyAttributes::Osu(attributes)) if score.is_relax() => {
+                self.relax.insert(key, attributes.into());
+            }
+            Some(attributes) if !score.is_relax() => {
+                self.rosu.insert(key, attributes.into());
+            }
+            _ => {}
+        }
+    }
+
+    /// Saves the attributes calculated since the last save to the attributes store.
+    pub async fn save(&mut self, beatmap_md5: &str, context: Arc<Context>) {
+        for key in std::mem::take(&mut self.unsaved) {
+            let Some(stored_key) = key.stored(beatmap_md5) else {
+                continue;
+            };
+
+            let attributes = match (self.relax.get(&key), self.rosu.get(&key)) {
+                (Some(relax), _) => StoredDifficultyAttributes::Osu(relax.into()),
+                (None, Some(rosu)) => rosu.into(),
+                (None, None) => continue,
+            };
+
+            usecases::difficulty_attributes::store(&stored_key, &attributes, context.clone()).await;
+        }
+    }
+}
+
+fn calculate_relax_pp(
+    score: &ScoreState,
+    beatmap: &Beatmap,
+    difficulty: &OsuDifficultyAttributes,
+) -> CalculateResponse {
+    let mut calculate = akatsuki_pp_rs::osu_2019::OsuPP::from_attributes(difficulty.clone())
+        .mods(score.mods as u32)
+        .combo(score.max_combo as u32);
+
+    if let Some(passed_objects) = score.passed_objects {
+        calculate = calculate.passed_objects(passed_objects as usize);
+    }
 
-    calculate = calculate.misses(request.miss_count as u32);
-    if request.accuracy.is_some() {
-        calculate = calculate.accuracy(request.accuracy.unwrap());
+    calculate = calculate.misses(score.miss_count as u32);
+    if score.accuracy.is_some() {
+        calculate = calculate.accuracy(score.accuracy.unwrap());
     } else {
         calculate = calculate
-            .n300(request.count_300.unwrap() as u32)
-            .n100(request.count_100.unwrap() as u32)
-            .n50(request.count_50.unwrap() as u32);
+            .n300(score.count_300.unwrap() as u32)
+            .n100(score.count_100.unwrap() as u32)
+            .n50(score.count_50.unwrap() as u32);
     }
 
     let result = calculate.calculate();
@@ -76,56 +611,114 @@ async fn calculate_relax_pp(
         stars = 0.0;
     }
 
-    Ok(CalculateResponse {
-        stars,
-        pp,
+    CalculateResponse {
         ar: result.difficulty.ar as f32,
         od: result.difficulty.od as f32,
         max_combo: result.difficulty.max_combo as i32,
-    })
+        aim_pp: Some(round(result.pp_aim as f32, 2)),
+        speed_pp: Some(round(result.pp_speed as f32, 2)),
+        accuracy_pp: Some(round(result.pp_acc as f32, 2)),
+        flashlight_pp: Some(round(result.pp_flashlight as f32, 2)),
+        n_circles: Some(result.difficulty.n_circles as i32),
+        n_sliders: Some(result.difficulty.n_sliders as i32),
+        n_spinners: Some(result.difficulty.n_spinners as i32),
+        ..CalculateResponse::new(stars, pp, beatmap, score)
+    }
 }
 
-async fn calculate_rosu_pp(
-    request: &CalculateRequest,
-    context: Arc<Context>,
-) -> anyhow::Result<CalculateResponse> {
-    let beatmap_bytes =
-        usecases::beatmaps::fetch_beatmap_osu_file(request.beatmap_id, context).await?;
-    let beatmap = Beatmap::from_bytes(&beatmap_bytes)?;
+fn calculate_rosu_difficulty(
+    score: &ScoreState,
+    beatmap: &Beatmap,
+) -> anyhow::Result<DifficultyAttributes> {
+    let calculate = beatmap
+        .performance()
+        .try_mode(Mode::try_from(score.mode)?.game_mode())
+        .map_err(|_| anyhow!("failed to set mode {} for beatmap", score.mode))?
+        .mods(score.mods as u32)
+        .lazer(score.is_lazer());
 
-    let mut calculate = beatmap
+    Ok(with_difficulty_settings(calculate, score)
+        .calculate()
+        .difficulty_attributes())
+}
+
+fn with_difficulty_settings<'map>(
+    mut calculate: Performance<'map>,
+    score: &ScoreState,
+) -> Performance<'map> {
+    if let Some(ar) = score.ar {
+        calculate = calculate.ar(ar, false);
+    }
+    if let Some(od) = score.od {
+        calculate = calculate.od(od, false);
+    }
+    if let Some(cs) = score.cs {
+        calculate = calculate.cs(cs, false);
+    }
+    if let Some(hp) = score.hp {
+        calculate = calculate.hp(hp, false);
+    }
+    if let Some(clock_rate) = score.clock_rate {
+        calculate = calculate.clock_rate(clock_rate);
+    }
+    if let Some(passed_objects) = score.passed_objects {
+        calculate = calculate.passed_objects(passed_objects as u32);
+    }
+
+    calculate
+}
+
+fn calculate_rosu_pp(
+    score: &ScoreState,
+    beatmap: &Beatmap,
+    difficulty: &DifficultyAttributes,
+) -> CalculateResponse {
+    let mut calculate = difficulty
+        .clone()
         .performance()
-        .try_mode(match request.mode {
-            0 => GameMode::Osu,
-            1 => GameMode::Taiko,
-            2 => GameMode::Catch,
-            3 => GameMode::Mania,
-            _ => unreachable!(),
-        })
-        .map_err(|_| {
-            anyhow!(
-                "failed to set mode {} for beatmap {}",
-                request.mode,
-                request.beatmap_id
-            )
-        })?
-        .mods(request.mods as u32)
-        .lazer(false)
-        .combo(request.max_combo as u32);
-
-    calculate = calculate.misses(request.miss_count as u32);
-    if request.accuracy.is_some() {
-        calculate = calculate.accuracy(request.accuracy.unwrap() as f64);
+        .mods(score.mods as u32)
+        .lazer(score.is_lazer())
+        .combo(score.max_combo as u32);
+
+    calculate = with_difficulty_settings(calculate, score);
+    calculate = calculate.misses(score.miss_count as u32);
+    if score.accuracy.is_some() {
+        calculate = calculate.accuracy(score.accuracy.unwrap() as f64);
     } else {
         calculate = calculate
-            .n300(request.count_300.unwrap() as u32)
-            .n100(request.count_100.unwrap() as u32)
-            .n50(request.count_50.unwrap() as u32);
+            .n300(score.count_300.unwrap() as u32)
+            .n100(score.count_100.unwrap() as u32)
+            .n50(score.count_50.unwrap() as u32);
+
+        if let Some(count_geki) = score.count_geki {
+            calculate = calculate.n_geki(count_geki as u32);
+        }
+        if let Some(count_katu) = score.count_katu {
+            calculate = calculate.n_katu(count_katu as u32);
+        }
+    }
+
+    if score.is_lazer() {
+        if let Some(slider_end_hits) = score.slider_end_hits {
+            calculate = calculate.slider_end_hits(slider_end_hits as u32);
+        }
+        if let Some(large_tick_hits) = score.large_tick_hits {
+            calculate = calculate.large_tick_hits(large_tick_hits as u32);
+        }
+        if let Some(small_tick_hits) = score.small_tick_hits {
+            calculate = calculate.small_tick_hits(small_tick_hits as u32);
+        }
     }
 
     let result = calculate.calculate();
+    let total_pp = match &result {
+        PerformanceAttributes::Osu(result) if score.is_autopilot() => {
+            usecases::performance::autopilot_pp(result)
+        }
+        _ => result.pp(),
+    };
 
-    let mut pp = round(result.pp() as f32, 2);
+    let mut pp = round(total_pp as f32, 2);
     if pp.is_infinite() || pp.is_nan() {
         log::warn!("Calculated pp is infinite or NaN, setting to 0");
         pp = 0.0;
@@ -137,87 +730,1122 @@ async fn calculate_rosu_pp(
         stars = 0.0;
     }
 
-    Ok(match result {
+    let response = CalculateResponse {
+        lazer: score.is_lazer(),
+        ..CalculateResponse::new(stars, pp, beatmap, score)
+    };
+
+    match result {
         PerformanceAttributes::Osu(result) => CalculateResponse {
-            stars,
-            pp,
             ar: result.difficulty.ar as f32,
             od: result.difficulty.od as f32,
             max_combo: result.difficulty.max_combo as i32,
+            aim_pp: Some(match score.is_autopilot() {
+                true => 0.0,
+                false => round(result.pp_aim as f32, 2),
+            }),
+            speed_pp: Some(round(result.pp_speed as f32, 2)),
+            accuracy_pp: Some(round(result.pp_acc as f32, 2)),
+            flashlight_pp: Some(match score.is_autopilot() {
+                true => 0.0,
+                false => round(result.pp_flashlight as f32, 2),
+            }),
+            n_circles: Some(result.difficulty.n_circles as i32),
+            n_sliders: Some(result.difficulty.n_sliders as i32),
+            n_spinners: Some(result.difficulty.n_spinners as i32),
+            ..response
         },
         PerformanceAttributes::Taiko(result) => CalculateResponse {
-            stars,
-            pp,
-            ar: 0.0,
-            od: 0.0,
             max_combo: result.difficulty.max_combo as i32,
+            accuracy_pp: Some(round(result.pp_acc as f32, 2)),
+            difficulty_pp: Some(round(result.pp_difficulty as f32, 2)),
+            ..response
         },
         PerformanceAttributes::Catch(result) => CalculateResponse {
-            stars,
-            pp,
-            ar: 0.0,
-            od: 0.0,
+            ar: result.difficulty.ar as f32,
             max_combo: result.difficulty.max_combo() as i32,
+            n_fruits: Some(result.difficulty.n_fruits as i32),
+            n_droplets: Some(result.difficulty.n_droplets as i32),
+            n_tiny_droplets: Some(result.difficulty.n_tiny_droplets as i32),
+            ..response
         },
         PerformanceAttributes::Mania(result) => CalculateResponse {
-            stars,
-            pp,
-            ar: 0.0,
-            od: 0.0,
             max_combo: result.difficulty.max_combo as i32,
+            difficulty_pp: Some(round(result.pp_difficulty as f32, 2)),
+            n_objects: Some(result.difficulty.n_objects as i32),
+            ..response
         },
+    }
+}
+
+pub(super) const RX: i32 = mods::RELAX as i32;
+pub(super) const AP: i32 = mods::AUTOPILOT as i32;
+
+pub fn calculate_score(
+    score: &ScoreState,
+    beatmap: &Beatmap,
+    difficulty_cache: &mut DifficultyCache,
+) -> anyhow::Result<CalculateResponse> {
+    let is_convert = usecases::beatmaps::check_convert(beatmap, Mode::try_from(score.mode)?)?;
+
+    let response = if score.is_relax() {
+        let difficulty = difficulty_cache.relax(score, beatmap);
+        calculate_relax_pp(score, beatmap, difficulty)
+    } else {
+        let difficulty = difficulty_cache.rosu(score, beatmap)?;
+        calculate_rosu_pp(score, beatmap, difficulty)
+    };
+
+    Ok(CalculateResponse {
+        is_convert,
+        ..response
+    })
+}
+
+fn beatmap_max_combo(
+    score: &ScoreState,
+    beatmap: &Beatmap,
+    difficulty_cache: &mut DifficultyCache,
+) -> anyhow::Result<i32> {
+    usecases::beatmaps::check_convert(beatmap, Mode::try_from(score.mode)?)?;
+
+    if score.is_relax() {
+        Ok(difficulty_cache.relax(score, beatmap).max_combo as i32)
+    } else {
+        Ok(difficulty_cache.rosu(score, beatmap)?.max_combo() as i32)
+    }
+}
+
+fn fetch_error(beatmap_id: i32, error: anyhow::Error) -> CalculateError {
+    log::warn!(
+        beatmap_id = beatmap_id,
+        error = error.to_string();
+        "Failed to fetch beatmap",
+    );
+
+    CalculateError::from_fetch_error(beatmap_id, &error)
+}
+
+pub(super) async fn fetch_beatmap(
+    beatmap_id: i32,
+    context: Arc<Context>,
+) -> Result<Arc<CachedBeatmap>, CalculateError> {
+    usecases::beatmaps::fetch_beatmap(beatmap_id, context)
+        .await
+        .map_err(|e| fetch_error(beatmap_id, e))
+}
+
+async fn refresh_beatmap(
+    beatmap_id: i32,
+    context: Arc<Context>,
+) -> Result<Arc<CachedBeatmap>, CalculateError> {
+    usecases::beatmaps::refresh_beatmap(beatmap_id, context)
+        .await
+        .map_err(|e| fetch_error(beatmap_id, e))
+}
+
+pub(super) async fn fetch_verified_beatmap(
+    beatmap_id: i32,
+    expected_md5: &str,
+    context: Arc<Context>,
+) -> Result<Arc<CachedBeatmap>, CalculateError> {
+    usecases::beatmaps::fetch_verified_beatmap(beatmap_id, expected_md5, context)
+        .await
+        .map_err(|e| fetch_error(beatmap_id, e))
+}
+
+fn check_beatmap_md5(
+    beatmap_id: i32,
+    actual_md5: &str,
+    expected_md5: &str,
+) -> Result<(), CalculateError> {
+    usecases::beatmaps::verify_beatmap_md5(beatmap_id, actual_md5, expected_md5).map_err(|e| {
+        log::warn!(
+            beatmap_id = beatmap_id,
+            expected_md5 = expected_md5,
+            actual_md5 = actual_md5;
+            "Fetched beatmap does not match the requested md5",
+        );
+
+        CalculateError::new(CalculateErrorKind::BeatmapMd5Mismatch, e.to_string())
     })
 }
 
-const RX: i32 = 1 << 7;
+fn log_calculate_result(request: &CalculateRequest, result: &CalculateResult) {
+    match result {
+        CalculateResult::Ok(result) => log::info!(
+            performance_points = result.pp,
+            star_rating = result.stars,
+            beatmap_id = request.beatmap_id;
+            "Calculated performance for beatmap.",
+        ),
+        CalculateResult::Err { error } => log::error!(
+            beatmap_id = request.beatmap_id,
+            kind = error.kind.as_str(),
+            error = error.message.as_str();
+            "Performance calculation failed for beatmap",
+        ),
+    }
+}
+
+fn calculate_request(
+    request: &CalculateRequest,
+    beatmap: &CachedBeatmap,
+    difficulty_cache: &mut DifficultyCache,
+) -> CalculateResult {
+    if let Err(error) = check_beatmap_md5(request.beatmap_id, &beatmap.md5, &request.beatmap_md5) {
+        return CalculateResult::Err { error };
+    }
+
+    match calculate_score(&request.score, &beatmap.beatmap, difficulty_cache) {
+        Ok(result) => CalculateResult::Ok(result),
+        Err(e) => CalculateResult::Err {
+            error: CalculateError::from_calculation_error(&e),
+        },
+    }
+}
+
+async fn calculate_beatmap_requests(
+    beatmap_id: i32,
+    requests: Vec<(usize, CalculateRequest)>,
+    context: Arc<Context>,
+) -> Vec<(usize, CalculateResult)> {
+    let mut beatmap = fetch_beatmap(beatmap_id, context.clone()).await;
+    let mut refreshed = false;
+
+    let mut difficulty_cache = DifficultyCache::default();
+    let mut results = Vec::with_capacity(requests.len());
+
+    for (idx, request) in requests {
+        // the cached version may be older than the one the request was made for
+        let outdated = match &beatmap {
+            Ok(beatmap) => !beatmap.md5.eq_ignore_ascii_case(&request.beatmap_md5),
+            Err(_) => false,
+        };
+        if outdated && !refreshed {
+            if let Ok(beatmap) = &beatmap {
+                difficulty_cache.save(&beatmap.md5, context.clone()).await;
+            }
+
+            beatmap = refresh_beatmap(beatmap_id, context.clone()).await;
+            difficulty_cache = DifficultyCache::default();
+            refreshed = true;
+        }
+
+        let result = match &beatmap {
+            Ok(beatmap) => {
+                difficulty_cache
+                    .load(&request.score, &beatmap.md5, context.clone())
+                    .await;
+                calculate_request(&request, beatmap, &mut difficulty_cache)
+            }
+            Err(error) => CalculateResult::Err {
+                error: error.clone(),
+            },
+        };
+
+        log_calculate_result(&request, &result);
+        results.push((idx, result));
+    }
+
+    if let Ok(beatmap) = &beatmap {
+        difficulty_cache.save(&beatmap.md5, context).await;
+    }
+
+    results
+}
 
 async fn calculate_play(
     Extension(ctx): Extension<Arc<Context>>,
-    Json(requests): Json<Vec<CalculateRequest>>,
-) -> AppResult<impl IntoResponse> {
-    let mut results = Vec::new();
-
-    for request in requests {
-        let have_hit_statistics = request.count_300.is_some()
-            && request.count_100.is_some()
-            && request.count_50.is_some();
-        let have_accuracy = request.accuracy.is_some();
-
-        if (!have_accuracy && !have_hit_statistics) || (have_accuracy && have_hit_statistics) {
-            return Ok((
-                StatusCode::BAD_REQUEST,
-                "you must pass accuracy OR hit results",
-            )
-                .into_response());
-        }
+    Json(requests): Json<Vec<serde_json::Value>>,
+) -> AppResult<Json<Vec<CalculateResult>>> {
+    let mut results: Vec<Option<CalculateResult>> = (0..requests.len()).map(|_| None).collect();
+    let mut requests_by_beatmap: HashMap<i32, Vec<(usize, CalculateRequest)>> = HashMap::new();
 
-        let raw_result = if request.mods & RX > 0 && request.mode == 0 {
-            calculate_relax_pp(&request, ctx.clone()).await
-        } else {
-            calculate_rosu_pp(&request, ctx.clone()).await
+    for (idx, request) in requests.into_iter().enumerate() {
+        // items are deserialised one by one, so a malformed item only fails itself
+        let mut request = match serde_json::from_value::<CalculateRequest>(request) {
+            Ok(request) => request,
+            Err(e) => {
+                log::warn!(
+                    index = idx,
+                    error = e.to_string();
+                    "Invalid performance calculation request",
+                );
+
+                results[idx] = Some(CalculateResult::Err {
+                    error: CalculateError::new(
+                        CalculateErrorKind::InvalidInput,
+                        format!("invalid request: {}", e),
+                    ),
+                });
+                continue;
+            }
         };
 
-        let result = match raw_result {
-            Ok(result) => result,
+        if let Err(message) = request.score.prepare() {
+            let result = CalculateResult::Err {
+                error: CalculateError::new(CalculateErrorKind::InvalidInput, message),
+            };
+
+            log_calculate_result(&request, &result);
+            results[idx] = Some(result);
+            continue;
+        }
+
+        requests_by_beatmap
+            .entry(request.beatmap_id)
+            .or_default()
+            .push((idx, request));
+    }
+
+    let semaphore = Arc::new(Semaphore::new(MAX_CONCURRENT_BEATMAPS));
+    let mut futures = FuturesUnordered::new();
+
+    for (beatmap_id, beatmap_requests) in requests_by_beatmap {
+        let ctx = ctx.clone();
+        let permit = semaphore.clone().acquire_owned().await?;
+
+        futures.push(tokio::spawn(async move {
+            let beatmap_results =
+                calculate_beatmap_requests(beatmap_id, beatmap_requests, ctx).await;
+            drop(permit);
+
+            beatmap_results
+        }));
+    }
+
+    while let Some(result) = futures.next().await {
+        match result {
+            Ok(beatmap_results) => {
+                for (idx, result) in beatmap_results {
+                    results[idx] = Some(result);
+                }
+            }
             Err(e) => {
                 log::error!(
-                    beatmap_id = request.beatmap_id,
                     error = e.to_string();
-                    "Performance calculation failed for beatmap",
+                    "Performance calculation task failed",
                 );
-
-                return Err(e.into());
             }
+        }
+    }
+
+    let results = results
+        .into_iter()
+        .map(|result| {
+            result.unwrap_or_else(|| CalculateResult::Err {
+                error: CalculateError::new(
+                    CalculateErrorKind::CalculationFailed,
+                    "performance calculation task failed",
+                ),
+            })
+        })
+        .collect();
+
+    Ok(Json(results))
+}
+
+async fn calculate_osu_file(
+    Query(mut score): Query<ScoreState>,
+    ContentLengthLimit(osu_file): ContentLengthLimit<Bytes, MAX_OSU_FILE_SIZE>,
+) -> AppResult<impl IntoResponse> {
+    if let Err(message) = score.prepare() {
+        return Ok((StatusCode::BAD_REQUEST, message).into_response());
+    }
+
+    if osu_file.is_empty() {
+        return Ok((StatusCode::BAD_REQUEST, "missing .osu file in request body").into_response());
+    }
+
+    let beatmap_md5 = format!("{:x}", md5::compute(&osu_file));
+
+    let beatmap = match Beatmap::from_bytes(&osu_file) {
+        Ok(beatmap) => beatmap,
+        Err(e) => {
+            log::warn!(
+                beatmap_md5 = beatmap_md5,
+                error = e.to_string();
+                "Failed to parse uploaded .osu file",
+            );
+
+            return Ok((StatusCode::BAD_REQUEST, "failed to parse .osu file").into_response());
+        }
+    };
+
+    let result = match calculate_score(&score, &beatmap, &mut DifficultyCache::default()) {
+        Ok(result) => result,
+        Err(e) => {
+            let error = CalculateError::from_calculation_error(&e);
+            log::error!(
+                beatmap_md5 = beatmap_md5,
+                kind = error.kind.as_str(),
+                error = e.to_string();
+                "Performance calculation failed for uploaded beatmap",
+            );
+
+            return Ok((error.kind.status_code(), Json(error)).into_response());
+        }
+    };
+
+    log::info!(
+        performance_points = result.pp,
+        star_rating = result.stars,
+        beatmap_md5 = beatmap_md5;
+        "Calculated performance for uploaded beatmap.",
+    );
+
+    Ok(Json(result).into_response())
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct ReplayPerformance {
+    pub beatmap_id: i32,
+    pub beatmapset_id: i32,
+    pub song_name: String,
+    pub replay: ReplayHeader,
+    pub performance: CalculateResponse,
+}
+
+fn replay_score_state(replay: &ReplayHeader) -> ScoreState {
+    ScoreState {
+        mode: replay.mode,
+        mods: replay.mods,
+        max_combo: replay.max_combo,
+        accuracy: None,
+        count_300: Some(replay.count_300),
+        count_100: Some(replay.count_100),
+        count_50: Some(replay.count_50),
+        count_geki: Some(replay.count_geki),
+        count_katu: Some(replay.count_katu),
+        miss_count: replay.miss_count,
+        lazer: None,
+        slider_end_hits: None,
+        large_tick_hits: None,
+        small_tick_hits: None,
+        ar: None,
+        od: None,
+        cs: None,
+        hp: None,
+        clock_rate: None,
+        passed_objects: None,
+        mod_changes: Vec::new(),
+    }
+}
+
+async fn calculate_replay(
+    Extension(ctx): Extension<Arc<Context>>,
+    ContentLengthLimit(replay_file): ContentLengthLimit<Bytes, MAX_REPLAY_FILE_SIZE>,
+) -> AppResult<impl IntoResponse> {
+    let replay = match ReplayHeader::parse(&replay_file) {
+        Ok(replay) => replay,
+        Err(e) => return Ok((StatusCode::BAD_REQUEST, e.to_string()).into_response()),
+    };
+
+    let Some(beatmap_info) =
+        usecases::beatmaps::fetch_by_md5(&replay.beatmap_md5, ctx.clone()).await?
+    else {
+        let error = CalculateError::new(
+            CalculateErrorKind::BeatmapNotFound,
+            format!("no beatmap with md5 {}", replay.beatmap_md5),
+        );
+        return Ok((error.kind.status_code(), Json(error)).into_response());
+    };
+
+    let beatmap =
+        match fetch_verified_beatmap(beatmap_info.beatmap_id, &replay.beatmap_md5, ctx.clone())
+            .await
+        {
+            Ok(beatmap) => beatmap,
+            Err(e) => return Ok((e.kind.status_code(), Json(e)).into_response()),
         };
 
-        log::info!(
-            performance_points = result.pp,
-            star_rating = result.stars,
-            beatmap_id = request.beatmap_id;
-            "Calculated performance for beatmap.",
+    let mut score = replay_score_state(&replay);
+    if let Err(message) = score.prepare() {
+        return Ok((StatusCode::BAD_REQUEST, message).into_response());
+    }
+
+    let mut difficulty_cache = DifficultyCache::default();
+    difficulty_cache
+        .load(&score, &beatmap.md5, ctx.clone())
+        .await;
+    let performance = calculate_score(&score, &beatmap.beatmap, &mut difficulty_cache);
+    difficulty_cache.save(&beatmap.md5, ctx.clone()).await;
+
+    let performance = match performance {
+        Ok(performance) => performance,
+        Err(e) => {
+            let error = CalculateError::from_calculation_error(&e);
+            log::error!(
+                beatmap_id = beatmap_info.beatmap_id,
+                replay_md5 = replay.replay_md5,
+                kind = error.kind.as_str(),
+                error = e.to_string();
+                "Performance calculation failed for replay",
+            );
+
+            return Ok((error.kind.status_code(), Json(error)).into_response());
+        }
+    };
+
+    log::info!(
+        performance_points = performance.pp,
+        star_rating = performance.stars,
+        beatmap_id = beatmap_info.beatmap_id,
+        replay_md5 = replay.replay_md5,
+        player_name = replay.player_name;
+        "Calculated performance for replay.",
+    );
+
+    Ok(Json(ReplayPerformance {
+        beatmap_id: beatmap_info.beatmap_id,
+        beatmapset_id: beatmap_info.beatmapset_id,
+        song_name: beatmap_info.song_name,
+        replay,
+        performance,
+    })
+    .into_response())
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct CalculateQuery {
+    /// Defaults to the beatmap's own mode.
+    pub mode: Option<i32>,
+    #[serde(default, deserialize_with = "mods::deserialize")]
+    pub mods: i32,
+    /// Defaults to 100%.
+    pub acc: Option<f32>,
+    /// Defaults to the beatmap's max combo.
+    pub combo: Option<i32>,
+    #[serde(default)]
+    pub misses: i32,
+    pub lazer: Option<bool>,
+}
+
+impl CalculateQuery {
+    fn score_state(&self, mode: i32) -> ScoreState {
+        ScoreState {
+            mode,
+            mods: self.mods,
+            max_combo: self.combo.unwrap_or(0),
+            accuracy: Some(self.acc.unwrap_or(100.0)),
+            count_300: None,
+            count_100: None,
+            count_50: None,
+            count_geki: None,
+            count_katu: None,
+            miss_count: self.misses,
+            lazer: self.lazer,
+            slider_end_hits: None,
+            large_tick_hits: None,
+            small_tick_hits: None,
+            ar: None,
+            od: None,
+            cs: None,
+            hp: None,
+            clock_rate: None,
+            passed_objects: None,
+            mod_changes: Vec::new(),
+        }
+    }
+}
+
+async fn calculate_beatmap(
+    Extension(ctx): Extension<Arc<Context>>,
+    Path(beatmap_id): Path<i32>,
+    Query(query): Query<CalculateQuery>,
+) -> AppResult<impl IntoResponse> {
+    if matches!(query.acc, Some(acc) if !(0.0..=100.0).contains(&acc)) {
+        return Ok((StatusCode::BAD_REQUEST, "acc must be between 0 and 100").into_response());
+    }
+
+    if query.misses < 0 || matches!(query.combo, Some(combo) if combo < 0) {
+        return Ok((
+            StatusCode::BAD_REQUEST,
+            "combo and misses must not be negative",
+        )
+            .into_response());
+    }
+
+    let beatmap = match fetch_beatmap(beatmap_id, ctx.clone()).await {
+        Ok(beatmap) => beatmap,
+        Err(e) => return Ok((e.kind.status_code(), Json(e)).into_response()),
+    };
+
+    let mode = query
+        .mode
+        .unwrap_or_else(|| Mode::from(beatmap.beatmap.mode).as_i32());
+    let mut score = query.score_state(mode);
+    if let Err(message) = score.prepare() {
+        return Ok((StatusCode::BAD_REQUEST, message).into_response());
+    }
+
+    let mut difficulty_cache = DifficultyCache::default();
+    difficulty_cache
+        .load(&score, &beatmap.md5, ctx.clone())
+        .await;
+    let result =
+        beatmap_max_combo(&score, &beatmap.beatmap, &mut difficulty_cache).and_then(|max_combo| {
+            score.max_combo = query.combo.unwrap_or(max_combo);
+            calculate_score(&score, &beatmap.beatmap, &mut difficulty_cache)
+        });
+    difficulty_cache.save(&beatmap.md5, ctx.clone()).await;
+
+    let result = match result {
+        Ok(result) => result,
+        Err(e) => {
+            let error = CalculateError::from_calculation_error(&e);
+            log::error!(
+                beatmap_id = beatmap_id,
+                kind = error.kind.as_str(),
+                error = e.to_string();
+                "Performance calculation failed for beatmap",
+            );
+
+            return Ok((error.kind.status_code(), Json(error)).into_response());
+        }
+    };
+
+    log::info!(
+        performance_points = result.pp,
+        star_rating = result.stars,
+        beatmap_id = beatmap_id;
+        "Calculated performance for beatmap.",
+    );
+
+    Ok((
+        Headers(vec![(header::CACHE_CONTROL, CALCULATE_CACHE_CONTROL)]),
+        Json(result),
+    )
+        .into_response())
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct PpTableRequest {
+    pub beatmap_id: i32,
+    pub mode: i32,
+    #[serde(deserialize_with = "mods::deserialize")]
+    pub mods: i32,
+    pub lazer: Option<bool>,
+    pub ar: Option<f32>,
+    pub od: Option<f32>,
+    pub cs: Option<f32>,
+    pub hp: Option<f32>,
+    pub clock_rate: Option<f64>,
+    /// Defaults to 95, 97, 98, 99 and 100%.
+    pub accuracies: Option<Vec<f32>>,
+    /// The play to calculate current & if-FC pp for, if any.
+    pub current: Option<PlayState>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct PlayState {
+    pub max_combo: i32,
+    pub accuracy: Option<f32>,
+    pub count_300: Option<i32>,
+    pub count_100: Option<i32>,
+    pub count_50: Option<i32>,
+    pub count_geki: Option<i32>,
+    pub count_katu: Option<i32>,
+    pub miss_count: i32,
+}
+
+impl PpTableRequest {
+    fn score_state(&self, max_combo: i32, play: Option<&PlayState>) -> ScoreState {
+        ScoreState {
+            mode: self.mode,
+            mods: self.mods,
+            max_combo: play.map_or(max_combo, |play| play.max_combo),
+            accuracy: play.map_or(Some(100.0), |play| play.accuracy),
+            count_300: play.and_then(|play| play.count_300),
+            count_100: play.and_then(|play| play.count_100),
+            count_50: play.and_then(|play| play.count_50),
+            count_geki: play.and_then(|play| play.count_geki),
+            count_katu: play.and_then(|play| play.count_katu),
+            miss_count: play.map_or(0, |play| play.miss_count),
+            lazer: self.lazer,
+            slider_end_hits: None,
+            large_tick_hits: None,
+            small_tick_hits: None,
+            ar: self.ar,
+            od: self.od,
+            cs: self.cs,
+            hp: self.hp,
+            clock_rate: self.clock_rate,
+            passed_objects: None,
+            mod_changes: Vec::new(),
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct AccuracyPp {
+    pub accuracy: f32,
+    pub pp: f32,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct CurrentPp {
+    pub pp: f32,
+    pub if_fc_pp: f32,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct PpTableResponse {
+    pub stars: f32,
+    pub mods: i32,
+    pub mod_acronyms: String,
+    pub mod_changes: Vec<String>,
+    pub max_combo: i32,
+    pub lazer: bool,
+    pub is_convert: bool,
+    pub accuracies: Vec<AccuracyPp>,
+    pub current: Option<CurrentPp>,
+}
+
+/// How many hit results accuracy is worked out over, read from a calculation on the map.
+fn judgement_count(result: &CalculateResponse) -> i32 {
+    match (result.n_circles, result.n_fruits, result.n_objects) {
+        (Some(n_circles), _, _) => {
+            n_circles + result.n_sliders.unwrap_or(0) + result.n_spinners.unwrap_or(0)
+        }
+        (_, Some(n_fruits), _) => {
+            n_fruits + result.n_droplets.unwrap_or(0) + result.n_tiny_droplets.unwrap_or(0)
+        }
+        (_, _, Some(n_objects)) => n_objects,
+        // every taiko hit object that gives a judgement also gives combo
+        _ => result.max_combo,
+    }
+}
+
+/// The accuracy of a play once its misses are turned into 300s, each of which is worth a
+/// full judgement more than the miss was.
+fn fc_accuracy(accuracy: f32, miss_count: i32, judgement_count: i32) -> f32 {
+    if judgement_count <= 0 {
+        return accuracy;
+    }
+
+    (accuracy + 100.0 * miss_count as f32 / judgement_count as f32).min(100.0)
+}
+
+fn calculate_pp_table_rows(
+    request: &PpTableRequest,
+    accuracies: &[f32],
+    beatmap: &Beatmap,
+    difficulty_cache: &mut DifficultyCache,
+) -> anyhow::Result<PpTableResponse> {
+    let max_combo = beatmap_max_combo(&request.score_state(0, None), beatmap, difficulty_cache)?;
+
+    let mut stars = 0.0;
+    let mut lazer = false;
+    let mut is_convert = false;
+    let mut rows = Vec::with_capacity(accuracies.len());
+    for &accuracy in accuracies {
+        let score = ScoreState {
+            accuracy: Some(accuracy),
+            ..request.score_state(max_combo, None)
+        };
+
+        let result = calculate_score(&score, beatmap, difficulty_cache)?;
+        stars = result.stars;
+        lazer = result.lazer;
+        is_convert = result.is_convert;
+        rows.push(AccuracyPp {
+            accuracy,
+            pp: result.pp,
+        });
+    }
+
+    let current = match &request.current {
+        Some(play) => {
+            let score = request.score_state(max_combo, Some(play));
+            let result = calculate_score(&score, beatmap, difficulty_cache)?;
+
+            // misses become 300s, everything else about the play stays the same
+            let fc_score = ScoreState {
+                max_combo,
+                accuracy: score.accuracy.map(|accuracy| {
+                    fc_accuracy(accuracy, score.miss_count, judgement_count(&result))
+                }),
+                count_300: score.count_300.map(|n300| n300 + score.miss_count),
+                miss_count: 0,
+                ..request.score_state(max_combo, Some(play))
+            };
+            let fc_result = calculate_score(&fc_score, beatmap, difficulty_cache)?;
+
+            Some(CurrentPp {
+                pp: result.pp,
+                if_fc_pp: fc_result.pp,
+            })
+        }
+        None => None,
+    };
+
+    Ok(PpTableResponse {
+        stars,
+        mods: request.mods,
+        mod_acronyms: mods::format(request.mods as u32),
+        mod_changes: Vec::new(),
+        max_combo,
+        lazer,
+        is_convert,
+        accuracies: rows,
+        current,
+    })
+}
+
+async fn calculate_pp_table(
+    Extension(ctx): Extension<Arc<Context>>,
+    Json(mut request): Json<PpTableRequest>,
+) -> AppResult<impl IntoResponse> {
+    let accuracies = request
+        .accuracies
+        .clone()
+        .unwrap_or_else(|| DEFAULT_PP_TABLE_ACCURACIES.to_vec());
+
+    if accuracies.is_empty() || accuracies.len() > MAX_PP_TABLE_ROWS {
+        return Ok((
+            StatusCode::BAD_REQUEST,
+            format!(
+                "accuracies must have between 1 and {} entries",
+                MAX_PP_TABLE_ROWS
+            ),
+        )
+            .into_response());
+    }
+
+    if accuracies
+        .iter()
+        .any(|accuracy| !(0.0..=100.0).contains(accuracy))
+    {
+        return Ok((
+            StatusCode::BAD_REQUEST,
+            "accuracies must be between 0 and 100",
+        )
+            .into_response());
+    }
+
+    let mut current_state = request.score_state(0, request.current.as_ref());
+    if let Err(message) = current_state.prepare() {
+        return Ok((StatusCode::BAD_REQUEST, message).into_response());
+    }
+    request.mods = current_state.mods;
+
+    let beatmap = match fetch_beatmap(request.beatmap_id, ctx.clone()).await {
+        Ok(beatmap) => beatmap,
+        Err(e) => return Ok((e.kind.status_code(), Json(e)).into_response()),
+    };
+
+    let mut difficulty_cache = DifficultyCache::default();
+    difficulty_cache
+        .load(&request.score_state(0, None), &beatmap.md5, ctx.clone())
+        .await;
+    let response = calculate_pp_table_rows(
+        &request,
+        &accuracies,
+        &beatmap.beatmap,
+        &mut difficulty_cache,
+    );
+    difficulty_cache.save(&beatmap.md5, ctx.clone()).await;
+
+    let response = match response {
+        Ok(response) => PpTableResponse {
+            mods: request.mods,
+            mod_acronyms: mods::format(request.mods as u32),
+            mod_changes: current_state.mod_changes,
+            ..response
+        },
+        Err(e) => {
+            let error = CalculateError::from_calculation_error(&e);
+            log::error!(
+                beatmap_id = request.beatmap_id,
+                kind = error.kind.as_str(),
+                error = e.to_string();
+                "Performance calculation failed for pp table",
+            );
+
+            return Ok((error.kind.status_code(), Json(error)).into_response());
+        }
+    };
+
+    log::info!(
+        beatmap_id = request.beatmap_id,
+        mode = request.mode,
+        mods = request.mods,
+        rows = response.accuracies.len();
+        "Calculated pp table.",
+    );
+
+    Ok(Json(response).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accuracy_score(mode: i32, mods: i32) -> ScoreState {
+        CalculateQuery {
+            mode: None,
+            mods,
+            acc: Some(98.5),
+            combo: None,
+            misses: 0,
+            lazer: None,
+        }
+        .score_state(mode)
+    }
+
+    #[test]
+    fn validate_accepts_accuracy_or_hit_results() {
+        assert!(accuracy_score(0, 0).validate().is_ok());
+
+        let hit_results = ScoreState {
+            accuracy: None,
+            count_300: Some(500),
+            count_100: Some(10),
+            count_50: Some(1),
+            ..accuracy_score(1, 0)
+        };
+        assert!(hit_results.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_needs_exactly_one_of_accuracy_and_hit_results() {
+        let both = ScoreState {
+            count_300: Some(500),
+            count_100: Some(10),
+            count_50: Some(1),
+            ..accuracy_score(0, 0)
+        };
+        assert_eq!(
+            both.validate(),
+            Err("you must pass accuracy OR hit results")
+        );
+
+        let neither = ScoreState {
+            accuracy: None,
+            count_300: Some(500),
+            ..accuracy_score(0, 0)
+        };
+        assert_eq!(
+            neither.validate(),
+            Err("you must pass accuracy OR hit results")
+        );
+    }
+
+    #[test]
+    fn validate_rejects_unknown_modes() {
+        assert!(accuracy_score(4, 0).validate().is_err());
+        assert!(accuracy_score(-1, 0).validate().is_err());
+    }
+
+    #[test]
+    fn validate_rejects_lazer_relax() {
+        let score = ScoreState {
+            lazer: Some(true),
+            ..accuracy_score(0, RX)
+        };
+        assert_eq!(
+            score.validate(),
+            Err("lazer calculations are not supported for relax scores")
+        );
+
+        // relax is only its own calculator in std
+        let taiko = ScoreState {
+            lazer: Some(true),
+            ..accuracy_score(1, RX)
+        };
+        assert!(taiko.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_rejects_relax_with_autopilot() {
+        assert_eq!(
+            accuracy_score(0, RX | AP).validate(),
+            Err("relax and autopilot cannot be combined")
+        );
+    }
+
+    #[test]
+    fn validate_checks_difficulty_overrides() {
+        let in_range = ScoreState {
+            ar: Some(10.0),
+            od: Some(0.0),
+            cs: Some(11.0),
+            ..accuracy_score(0, 0)
+        };
+        assert!(in_range.validate().is_ok());
+
+        let out_of_range = ScoreState {
+            hp: Some(11.5),
+            ..accuracy_score(0, 0)
+        };
+        assert_eq!(
+            out_of_range.validate(),
+            Err("ar, od, cs and hp must be between 0 and 11")
         );
-        results.push(result);
     }
 
-    Ok(Json(results).into_response())
+    #[test]
+    fn validate_checks_passed_objects() {
+        let score = ScoreState {
+            passed_objects: Some(0),
+            ..accuracy_score(0, 0)
+        };
+        assert_eq!(score.validate(), Err("passed_objects must be at least 1"));
+    }
+
+    #[test]
+    fn validate_checks_clock_rate_for_every_calculator() {
+        for mods in [0, RX, AP] {
+            let score = ScoreState {
+                clock_rate: Some(1.2),
+                ..accuracy_score(0, mods)
+            };
+            assert!(score.validate().is_ok());
+
+            let score = ScoreState {
+                clock_rate: Some(2.5),
+                ..accuracy_score(0, mods)
+            };
+            assert_eq!(
+                score.validate(),
+                Err("clock_rate must be between 0.5 and 2.0")
+            );
+        }
+    }
+
+    #[test]
+    fn validate_rejects_negative_counts() {
+        let hit_results = ScoreState {
+            accuracy: None,
+            count_300: Some(500),
+            count_100: Some(10),
+            count_50: Some(1),
+            ..accuracy_score(0, 0)
+        };
+
+        let cases = [
+            (
+                ScoreState {
+                    count_300: Some(-1),
+                    ..hit_results.clone()
+                },
+                "count_300 must not be negative",
+            ),
+            (
+                ScoreState {
+                    count_100: Some(-1),
+                    ..hit_results.clone()
+                },
+                "count_100 must not be negative",
+            ),
+            (
+                ScoreState {
+                    count_50: Some(-1),
+                    ..hit_results.clone()
+                },
+                "count_50 must not be negative",
+            ),
+            (
+                ScoreState {
+                    count_geki: Some(-1),
+                    ..hit_results.clone()
+                },
+                "count_geki must not be negative",
+            ),
+            (
+                ScoreState {
+                    count_katu: Some(-1),
+                    ..hit_results.clone()
+                },
+                "count_katu must not be negative",
+            ),
+            (
+                ScoreState {
+                    miss_count: -1,
+                    ..accuracy_score(0, 0)
+                },
+                "miss_count must not be negative",
+            ),
+            (
+                ScoreState {
+                    max_combo: -1,
+                    ..accuracy_score(0, 0)
+                },
+                "max_combo must not be negative",
+            ),
+        ];
+        for (score, error) in cases {
+            assert_eq!(score.validate(), Err(error));
+        }
+    }
+
+    /// A short std map of alternating jumps.
+    fn jumps_beatmap() -> Beatmap {
+        let mut osu = String::from(
+            "osu file format v14\n\n\
+             [General]\nMode: 0\n\n\
+             [Difficulty]\nHPDrainRate:5\nCircleSize:4\nOverallDifficulty:8\n\
+             ApproachRate:9\nSliderMultiplier:1.4\nSliderTickRate:1\n\n\
+             [TimingPoints]\n1000,300,4,2,0,60,1,0\n\n\
+             [HitObjects]\n",
+        );
+        for idx in 0..100 {
+            let (x, y) = if idx % 2 == 0 { (64, 96) } else { (448, 288) };
+            osu += &format!("{x},{y},{},1,0,0:0:0:0:\n", 1000 + idx * 150);
+        }
+
+        Beatmap::from_bytes(osu.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn fc_accuracy_turns_misses_into_300s() {
+        assert_eq!(fc_accuracy(95.0, 3, 100), 98.0);
+        assert_eq!(fc_accuracy(99.0, 5, 100), 100.0);
+        assert_eq!(fc_accuracy(95.0, 0, 100), 95.0);
+    }
+
+    #[test]
+    fn pp_table_fc_of_an_accuracy_play_raises_its_accuracy() {
+        let beatmap = jumps_beatmap();
+
+        for mods in [0, RX] {
+            let request = PpTableRequest {
+                beatmap_id: 1,
+                mode: 0,
+                mods,
+                lazer: None,
+                ar: None,
+                od: None,
+                cs: None,
+                hp: None,
+                clock_rate: None,
+                accuracies: None,
+                current: Some(PlayState {
+                    max_combo: 60,
+                    accuracy: Some(95.0),
+                    count_300: None,
+                    count_100: None,
+                    count_50: None,
+                    count_geki: None,
+                    count_katu: None,
+                    miss_count: 3,
+                }),
+            };
+
+            let table = calculate_pp_table_rows(
+                &request,
+                &[95.0, 98.0],
+                &beatmap,
+                &mut DifficultyCache::default(),
+            )
+            .unwrap();
+            let current = table.current.unwrap();
+
+            // the FC is also worth more than a full combo at the play's original accuracy
+            assert!(current.if_fc_pp >= current.pp);
+            assert!(current.if_fc_pp > table.accuracies[0].pp);
+        }
+    }
 }
@@ -1,3 +1,5 @@
+pub mod beatmaps;
 pub mod calculate;
 pub mod health;
 pub mod reworks;
+pub mod scores;
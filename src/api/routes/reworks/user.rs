@@ -101,20 +101,8 @@ async fn get_rework_stats(
         .fetch_one(ctx.database.get().await?.deref_mut())
         .await?;
 
-    let redis_leaderboard = match rework.rx {
-        0 => "leaderboard".to_string(),
-        1 => "relaxboard".to_string(),
-        2 => "autoboard".to_string(),
-        _ => unreachable!(),
-    };
-
-    let stats_prefix = match rework.mode {
-        0 => "std",
-        1 => "taiko",
-        2 => "ctb",
-        3 => "mania",
-        _ => unreachable!(),
-    };
+    let redis_leaderboard = rework.relax_kind()?.redis_leaderboard();
+    let stats_prefix = rework.mode()?.stats_prefix();
 
     let old_rank_idx: Option<i64> = redis_connection
         .zrevrank(
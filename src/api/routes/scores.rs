@@ -0,0 +1,150 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Extension, Path},
+    response::IntoResponse,
+    routing::get,
+    Json, Router,
+};
+use reqwest::StatusCode;
+
+use crate::{
+    api::error::AppResult,
+    context::Context,
+    models::{mode::RelaxKind, score::RippleScore},
+    usecases,
+};
+
+use super::calculate::{
+    calculate_score, fetch_verified_beatmap, CalculateError, CalculateErrorKind, CalculateResponse,
+    DifficultyCache, ScoreState, AP, RX,
+};
+
+pub fn router() -> Router {
+    Router::new().route(
+        "/api/v1/scores/:rx/:score_id/pp",
+        get(calculate_stored_score),
+    )
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct StoredScorePerformance {
+    pub score_id: i64,
+    pub rx: i32,
+    pub beatmap_id: i32,
+    pub beatmap_md5: String,
+    pub stored_pp: f32,
+    pub pp_difference: f32,
+    pub performance: CalculateResponse,
+}
+
+fn score_state(score: &RippleScore, rx: i32) -> ScoreState {
+    let mut mods = score.mods;
+    match rx {
+        1 => mods |= RX,
+        2 => mods |= AP,
+        _ => {}
+    }
+
+    let have_hit_results = score.has_hit_results();
+
+    ScoreState {
+        mode: score.play_mode,
+        mods,
+        max_combo: score.max_combo,
+        accuracy: (!have_hit_results).then(|| score.accuracy),
+        count_300: have_hit_results.then(|| score.count_300),
+        count_100: have_hit_results.then(|| score.count_100),
+        count_50: have_hit_results.then(|| score.count_50),
+        count_geki: have_hit_results.then(|| score.count_gekis),
+        count_katu: have_hit_results.then(|| score.count_katus),
+        miss_count: score.count_misses,
+        lazer: None,
+        slider_end_hits: None,
+        large_tick_hits: None,
+        small_tick_hits: None,
+        ar: None,
+        od: None,
+        cs: None,
+        hp: None,
+        clock_rate: None,
+        passed_objects: None,
+        mod_changes: Vec::new(),
+    }
+}
+
+async fn calculate_stored_score(
+    Extension(ctx): Extension<Arc<Context>>,
+    Path((rx, score_id)): Path<(i32, i64)>,
+) -> AppResult<impl IntoResponse> {
+    let scores_table = match RelaxKind::try_from(rx) {
+        Ok(relax_kind) => relax_kind.scores_table(),
+        Err(e) => return Ok((StatusCode::BAD_REQUEST, e.to_string()).into_response()),
+    };
+
+    let Some(score) = usecases::scores::fetch_one(scores_table, score_id, ctx.clone()).await?
+    else {
+        return Ok((StatusCode::NOT_FOUND, "score not found").into_response());
+    };
+
+    let beatmap =
+        match fetch_verified_beatmap(score.beatmap_id, &score.beatmap_md5, ctx.clone()).await {
+            Ok(beatmap) => beatmap,
+            Err(e) => return Ok((e.kind.status_code(), Json(e)).into_response()),
+        };
+
+    let mut state = score_state(&score, rx);
+    if let Err(message) = state.prepare() {
+        log::warn!(
+            score_id = score_id,
+            rx = rx,
+            error = message.clone();
+            "Stored score cannot be calculated",
+        );
+
+        let error = CalculateError::new(CalculateErrorKind::InvalidInput, message);
+        return Ok((error.kind.status_code(), Json(error)).into_response());
+    }
+
+    let mut difficulty_cache = DifficultyCache::default();
+    difficulty_cache
+        .load(&state, &beatmap.md5, ctx.clone())
+        .await;
+    let performance = calculate_score(&state, &beatmap.beatmap, &mut difficulty_cache);
+    difficulty_cache.save(&beatmap.md5, ctx.clone()).await;
+
+    let performance = match performance {
+        Ok(performance) => performance,
+        Err(e) => {
+            let error = CalculateError::from_calculation_error(&e);
+            log::error!(
+                score_id = score_id,
+                rx = rx,
+                kind = error.kind.as_str(),
+                error = e.to_string();
+                "Performance calculation failed for stored score",
+            );
+
+            return Ok((error.kind.status_code(), Json(error)).into_response());
+        }
+    };
+
+    log::info!(
+        score_id = score_id,
+        rx = rx,
+        stored_pp = score.pp,
+        performance_points = performance.pp;
+        "Recalculated performance for stored score.",
+    );
+
+    Ok(Json(StoredScorePerformance {
+        score_id,
+        rx,
+        beatmap_id: score.beatmap_id,
+        beatmap_md5: score.beatmap_md5,
+        stored_pp: score.pp,
+        pp_difference: performance.pp - score.pp,
+        performance,
+    })
+    .into_response())
+}
@@ -0,0 +1,384 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use akatsuki_pp_rs::Beatmap;
+use anyhow::{anyhow, Context as _};
+use clap::Parser;
+use performance_service::{
+    api::{calculate_score, DifficultyCache, ScoreState},
+    models::mods,
+    processor::{calculate_rework_pp, ReworkPlay, REWORK_CALCULATORS},
+};
+
+/// Runs the live and rework calculators over local .osu files, without connecting to
+/// the database, redis or rabbitmq.
+#[derive(clap::Parser)]
+struct Args {
+    /// Folder of .osu files.
+    #[clap(long)]
+    beatmaps: PathBuf,
+
+    /// Scores to calculate, as a JSON array or a CSV file with a header row.
+    #[clap(long)]
+    scores: PathBuf,
+
+    /// `live`, rework ids or rework names. Differences are relative to the first one.
+    #[clap(long, value_delimiter = ',', default_value = "live")]
+    calculators: Vec<String>,
+}
+
+#[derive(Clone, Copy)]
+enum Calculator {
+    Live,
+    Rework(i32, &'static str),
+}
+
+impl Calculator {
+    fn parse(name: &str) -> anyhow::Result<Self> {
+        if name == "live" {
+            return Ok(Self::Live);
+        }
+
+        REWORK_CALCULATORS
+            .iter()
+            .find(|(rework_id, rework_name)| rework_id.to_string() == name || *rework_name == name)
+            .map(|(rework_id, rework_name)| Self::Rework(*rework_id, rework_name))
+            .ok_or_else(|| {
+                anyhow!(
+                    "unknown calculator {}, expected live or one of: {}",
+                    name,
+                    REWORK_CALCULATORS
+                        .iter()
+                        .map(|(rework_id, rework_name)| format!("{} ({})", rework_id, rework_name))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })
+    }
+
+    fn name(self) -> String {
+        match self {
+            Self::Live => "live".to_string(),
+            Self::Rework(rework_id, rework_name) => format!("{} {}", rework_id, rework_name),
+        }
+    }
+
+    /// `None` when the calculator does not apply to the score, e.g. a rework for a
+    /// taiko score.
+    async fn calculate(
+        self,
+        score: &ScoreState,
+        beatmap_bytes: &[u8],
+    ) -> anyhow::Result<Option<f32>> {
+        match self {
+            Self::Live => {
+                let mut score = score.clone();
+                score.prepare().map_err(|e| anyhow!(e))?;
+
+                let beatmap = Beatmap::from_bytes(beatmap_bytes)?;
+                let result = calculate_score(&score, &beatmap, &mut DifficultyCache::default())?;
+
+                Ok(Some(result.pp))
+            }
+            Self::Rework(rework_id, _) => {
+                // the rework calculators are std only and need the full hit results
+                let (Some(count_300), Some(count_100), Some(count_50)) =
+                    (score.count_300, score.count_100, score.count_50)
+                else {
+                    return Ok(None);
+                };
+
+                if score.mode != 0 {
+                    return Ok(None);
+                }
+
+                let play = ReworkPlay {
+                    mods: score.mods,
+                    max_combo: score.max_combo,
+                    count_300,
+                    count_100,
+                    count_50,
+                    count_misses: score.miss_count,
+                };
+                let pp = calculate_rework_pp(rework_id, &play, beatmap_bytes).await?;
+
+                Ok(Some(pp))
+            }
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct ScoreSpec {
+    /// File name in the beatmaps folder, every .osu file in it if missing.
+    beatmap: Option<String>,
+    #[serde(flatten)]
+    score: ScoreState,
+}
+
+fn csv_value(column: &str, value: &str) -> serde_json::Value {
+    if column == "beatmap" {
+        return value.into();
+    }
+
+    if let Ok(value) = value.parse::<i64>() {
+        return value.into();
+    }
+    if let Ok(value) = value.parse::<f64>() {
+        return value.into();
+    }
+
+    match value {
+        "true" => true.into(),
+        "false" => false.into(),
+        _ => value.into(),
+    }
+}
+
+/// Splits a CSV row on commas outside double quotes, so mods can be written as
+/// `"+HR,NC"`; `""` inside quotes is a literal quote.
+fn split_csv_row(line: &str) -> anyhow::Result<Vec<String>> {
+    let mut values = Vec::new();
+    let mut value = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                value.push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => values.push(std::mem::take(&mut value).trim().to_string()),
+            _ => value.push(c),
+        }
+    }
+
+    if quoted {
+        return Err(anyhow!("unterminated quoted value"));
+    }
+    values.push(value.trim().to_string());
+
+    Ok(values)
+}
+
+/// Reads CSV rows into the same fields as the JSON spec. Blank lines are skipped, but
+/// errors still name the line of the file they are on.
+fn parse_csv(contents: &str) -> anyhow::Result<Vec<ScoreSpec>> {
+    let mut lines = contents
+        .lines()
+        .enumerate()
+        .map(|(idx, line)| (idx + 1, line))
+        .filter(|(_, line)| !line.trim().is_empty());
+    let (_, header) = lines.next().context("score spec is empty")?;
+    let header = split_csv_row(header).context("invalid score spec header")?;
+
+    lines
+        .map(|(line_number, line)| {
+            let values = split_csv_row(line)
+                .with_context(|| format!("invalid score spec on line {}", line_number))?;
+            if values.len() != header.len() {
+                return Err(anyhow!(
+                    "invalid score spec on line {}: expected {} columns, found {}",
+                    line_number,
+                    header.len(),
+                    values.len()
+                ));
+            }
+
+            let row: serde_json::Map<String, serde_json::Value> = header
+                .iter()
+                .zip(&values)
+                .filter(|(_, value)| !value.is_empty())
+                .map(|(column, value)| (column.clone(), csv_value(column, value)))
+                .collect();
+
+            serde_json::from_value(serde_json::Value::Object(row))
+                .with_context(|| format!("invalid score spec on line {}", line_number))
+        })
+        .collect()
+}
+
+fn read_score_specs(path: &Path) -> anyhow::Result<Vec<ScoreSpec>> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("failed to read score spec {}", path.display()))?;
+
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("csv") => parse_csv(&contents),
+        _ => serde_json::from_str(&contents).context("invalid score spec"),
+    }
+}
+
+fn beatmap_paths(folder: &Path, spec: &ScoreSpec) -> anyhow::Result<Vec<PathBuf>> {
+    if let Some(beatmap) = &spec.beatmap {
+        return Ok(vec![folder.join(beatmap)]);
+    }
+
+    let mut paths: Vec<PathBuf> = fs::read_dir(folder)
+        .with_context(|| format!("failed to read beatmap folder {}", folder.display()))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension() == Some(OsStr::new("osu")))
+        .collect();
+    paths.sort();
+
+    Ok(paths)
+}
+
+fn score_columns(path: &Path, score: &ScoreState) -> Vec<String> {
+    let hits = match score.accuracy {
+        Some(accuracy) => format!("{:.2}%", accuracy),
+        None => format!(
+            "{}/{}/{}",
+            score.count_300.unwrap_or(0),
+            score.count_100.unwrap_or(0),
+            score.count_50.unwrap_or(0)
+        ),
+    };
+
+    vec![
+        path.file_name()
+            .map_or_else(String::new, |name| name.to_string_lossy().into_owned()),
+        score.mode.to_string(),
+        mods::format(score.mods as u32),
+        score.max_combo.to_string(),
+        hits,
+        score.miss_count.to_string(),
+    ]
+}
+
+fn pp_column(pp: &anyhow::Result<Option<f32>>, baseline: &anyhow::Result<Option<f32>>) -> String {
+    match (pp, baseline) {
+        (Ok(Some(pp)), Ok(Some(baseline))) if pp != baseline => {
+            format!("{:.2} ({:+.2})", pp, pp - baseline)
+        }
+        (Ok(Some(pp)), _) => format!("{:.2}", pp),
+        (Ok(None), _) => "-".to_string(),
+        (Err(_), _) => "error".to_string(),
+    }
+}
+
+fn print_table(rows: &[Vec<String>]) {
+    let widths: Vec<usize> = (0..rows[0].len())
+        .map(|column| rows.iter().map(|row| row[column].len()).max().unwrap_or(0))
+        .collect();
+
+    for row in rows {
+        let line: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(value, width)| format!("{:<width$}", value, width = width))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+
+    let calculators = args
+        .calculators
+        .iter()
+        .map(|name| Calculator::parse(name))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let specs = read_score_specs(&args.scores)?;
+
+    let mut rows = vec![["beatmap", "mode", "mods", "combo", "acc/hits", "misses"]
+        .iter()
+        .map(|column| column.to_string())
+        .chain(calculators.iter().map(|calculator| calculator.name()))
+        .collect::<Vec<_>>()];
+
+    for spec in &specs {
+        for path in beatmap_paths(&args.beatmaps, spec)? {
+            let beatmap_bytes = fs::read(&path)
+                .with_context(|| format!("failed to read beatmap {}", path.display()))?;
+
+            let mut results = Vec::with_capacity(calculators.len());
+            for calculator in &calculators {
+                let result = calculator.calculate(&spec.score, &beatmap_bytes).await;
+                if let Err(e) = &result {
+                    eprintln!("{} on {}: {:#}", calculator.name(), path.display(), e);
+                }
+
+                results.push(result);
+            }
+
+            let mut row = score_columns(&path, &spec.score);
+            row.extend(results.iter().map(|pp| pp_column(pp, &results[0])));
+            rows.push(row);
+        }
+    }
+
+    print_table(&rows);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn csv_error(contents: &str) -> String {
+        match parse_csv(contents) {
+            Ok(_) => panic!("expected {:?} to be rejected", contents),
+            Err(e) => format!("{:#}", e),
+        }
+    }
+
+    #[test]
+    fn split_csv_row_handles_quotes() {
+        assert_eq!(
+            split_csv_row(r#"75.osu, 0 ,"+HR,NC",""#).unwrap(),
+            vec!["75.osu", "0", "+HR,NC", ""]
+        );
+        assert_eq!(
+            split_csv_row(r#""say ""hi""",x"#).unwrap(),
+            vec![r#"say "hi""#, "x"]
+        );
+        assert!(split_csv_row(r#"75.osu,"HD"#).is_err());
+    }
+
+    #[test]
+    fn parse_csv_reads_rows_into_score_specs() {
+        let specs = parse_csv(
+            "beatmap,mode,mods,max_combo,count_300,count_100,count_50,miss_count\n\
+             75.osu,0,HDDT,314,200,5,0,1\n\
+             \n\
+             ,1,\"+HR,NC\",500,,,,0\n",
+        )
+        .unwrap();
+
+        assert_eq!(specs.len(), 2);
+        assert_eq!(specs[0].beatmap.as_deref(), Some("75.osu"));
+        assert_eq!(specs[0].score.mods, 72);
+        assert_eq!(specs[0].score.count_300, Some(200));
+        assert_eq!(specs[0].score.miss_count, 1);
+        assert_eq!(specs[1].beatmap, None);
+        assert_eq!(specs[1].score.mode, 1);
+        assert_eq!(specs[1].score.mods, 16 | 512);
+        assert_eq!(specs[1].score.count_300, None);
+    }
+
+    #[test]
+    fn parse_csv_rejects_rows_with_the_wrong_column_count() {
+        let error = csv_error("beatmap,mode,mods,max_combo,miss_count\n75.osu,0,+HR,NC,314,0\n");
+
+        assert!(error.contains("line 2"), "{}", error);
+        assert!(error.contains("expected 5 columns, found 6"), "{}", error);
+    }
+
+    #[test]
+    fn parse_csv_errors_name_the_file_line() {
+        let error = csv_error("\nbeatmap,mode,mods,max_combo,miss_count\n\n\n75.osu,x,HD,314,0\n");
+
+        assert!(error.contains("line 5"), "{}", error);
+    }
+
+    #[test]
+    fn parse_csv_rejects_empty_files() {
+        assert!(csv_error(" \n\n").contains("score spec is empty"));
+    }
+}
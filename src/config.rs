@@ -1,3 +1,7 @@
+use std::path::PathBuf;
+
+use crate::models::beatmap_source::BeatmapSourceKind;
+
 #[derive(clap::Parser, Clone)]
 pub struct Config {
     #[clap(long, env)]
@@ -75,6 +79,57 @@ pub struct Config {
     #[clap(long, env)]
     pub aws_secret_access_key: String,
 
+    /// Comma separated, the first one is used until it fails.
+    #[clap(long, env, value_delimiter = ',')]
+    pub beatmaps_service_base_url: Vec<String>,
+
+    #[clap(long, env, value_enum, default_value = "http")]
+    pub beatmap_source: BeatmapSourceKind,
+
+    #[clap(long, env, default_value = "beatmaps/")]
+    pub beatmaps_s3_key_prefix: String,
+
     #[clap(long, env)]
-    pub beatmaps_service_base_url: String,
+    pub beatmaps_local_dir: Option<PathBuf>,
+
+    #[clap(long, env, default_value = "30")]
+    pub beatmap_fetch_timeout_secs: u64,
+
+    #[clap(long, env, default_value = "5")]
+    pub beatmap_fetch_connect_timeout_secs: u64,
+
+    #[clap(long, env, default_value = "3")]
+    pub beatmap_fetch_retries: u32,
+
+    #[clap(long, env, default_value = "250")]
+    pub beatmap_fetch_backoff_ms: u64,
+
+    #[clap(long, env, default_value = "5000")]
+    pub beatmap_fetch_max_backoff_ms: u64,
+
+    #[clap(long, env, default_value = "5")]
+    pub beatmap_source_failure_threshold: u32,
+
+    #[clap(long, env, default_value = "30")]
+    pub beatmap_source_cooldown_secs: u64,
+
+    /// How many outages background work waits out for one beatmap or queue request
+    /// before giving up on it.
+    #[clap(long, env, default_value = "10")]
+    pub beatmap_source_unavailable_retries: u32,
+
+    #[clap(long, env, default_value = "1000")]
+    pub beatmap_cache_max_entries: usize,
+
+    #[clap(long, env, default_value = "268435456")]
+    pub beatmap_cache_max_bytes: usize,
+
+    /// How long a cached map is served to lookups by beatmap id, which cannot tell
+    /// whether the map was updated since.
+    #[clap(long, env, default_value = "600")]
+    pub beatmap_cache_by_id_ttl_secs: u64,
+
+    /// How long difficulty attributes are kept in redis, 0 disables storing them.
+    #[clap(long, env, default_value = "2592000")]
+    pub difficulty_attributes_ttl_secs: u64,
 }
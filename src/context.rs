@@ -1,8 +1,16 @@
+use std::sync::Arc;
+
 use deadpool::managed::Pool;
 use lapin::Channel;
 use redis::Client;
 
-use crate::{config::Config, models::pool::DbPool};
+use crate::{
+    config::Config,
+    models::{
+        beatmap_cache::BeatmapCache, beatmap_source::BeatmapSource,
+        circuit_breaker::CircuitBreaker, pool::DbPool,
+    },
+};
 
 #[derive(Clone)]
 pub struct Context {
@@ -10,4 +18,7 @@ pub struct Context {
     pub database: Pool<DbPool>,
     pub amqp_channel: Channel,
     pub redis: Client,
+    pub beatmap_cache: Arc<BeatmapCache>,
+    pub beatmap_source: Arc<dyn BeatmapSource>,
+    pub beatmap_source_breaker: Arc<CircuitBreaker>,
 }
@@ -1,13 +1,27 @@
-use crate::{context::Context, usecases};
-use akatsuki_pp_rs::model::mode::GameMode;
+use crate::models::{
+    beatmap_cache::CachedBeatmap,
+    difficulty_attributes::{
+        DifficultyAttributesKey, StoredDifficultyAttributes, LIVE_CALCULATOR_VERSION,
+    },
+    mode::{Mode, RelaxKind},
+    mods, score,
+};
+use crate::{
+    context::Context,
+    usecases,
+    usecases::beatmaps::{BeatmapMd5Mismatch, IncompatibleConvert, UnparseableBeatmap},
+};
+use akatsuki_pp_rs::any::{DifficultyAttributes, Performance, PerformanceAttributes};
+use akatsuki_pp_rs::osu::OsuDifficultyAttributes;
 use akatsuki_pp_rs::Beatmap;
 use anyhow::{anyhow, Context as _};
 use futures::stream::FuturesUnordered;
 use futures::StreamExt;
 use redis::AsyncCommands;
 use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::{ops::DerefMut, sync::Arc, time::SystemTime};
 use tokio::sync::{Mutex, Semaphore};
 
@@ -25,10 +39,37 @@ struct LightweightScore {
     pub accuracy: f32,
     pub completed: i32,
 
+    #[sqlx(rename = "300_count")]
+    pub count_300: i32,
+
+    #[sqlx(rename = "100_count")]
+    pub count_100: i32,
+
+    #[sqlx(rename = "50_count")]
+    pub count_50: i32,
+
+    #[sqlx(rename = "katus_count")]
+    pub count_katus: i32,
+
+    #[sqlx(rename = "gekis_count")]
+    pub count_gekis: i32,
+
     #[sqlx(rename = "misses_count")]
     pub count_misses: i32,
 }
 
+impl LightweightScore {
+    fn has_hit_results(&self) -> bool {
+        score::has_hit_results(
+            self.count_300,
+            self.count_100,
+            self.count_50,
+            self.count_gekis,
+            self.count_katus,
+        )
+    }
+}
+
 #[derive(Clone, sqlx::FromRow)]
 struct ScoreStatus {
     pub id: i64,
@@ -39,13 +80,15 @@ struct ScoreStatus {
 #[derive(Clone)]
 struct RecalculationRun {
     dry_run: bool,
+    lazer: bool,
     dry_run_score_pp: Option<Arc<Mutex<HashMap<i64, f32>>>>,
 }
 
 impl RecalculationRun {
-    fn new(dry_run: bool, track_score_pp: bool) -> Self {
+    fn new(dry_run: bool, track_score_pp: bool, lazer: bool) -> Self {
         Self {
             dry_run,
+            lazer,
             dry_run_score_pp: (dry_run && track_score_pp)
                 .then(|| Arc::new(Mutex::new(HashMap::new()))),
         }
@@ -73,6 +116,7 @@ fn round(x: f32, decimals: u32) -> f32 {
 
 const MAX_CONCURRENT_BEATMAP_TASKS: usize = 10;
 const MAX_CONCURRENT_TASKS: usize = 100;
+const DEFAULT_PREFETCH_CONCURRENCY: usize = 16;
 const BATCH_SIZE: u32 = 1000;
 const MAX_DRY_RUN_TRACKED_SCORE_PPS: i64 = 100_000;
 
@@ -146,6 +190,32 @@ impl DeployFilters {
     }
 }
 
+/// Normalises stored mods like `ScoreState::prepare` does for requests, so e.g. NC scores
+/// share the attributes and calculation of DT scores. Scores with mods no real play can
+/// have are bad data and are skipped rather than failing the run.
+fn normalise_score_mods(scores: Vec<LightweightScore>, mode: Mode) -> Vec<LightweightScore> {
+    scores
+        .into_iter()
+        .filter_map(|mut score| match mods::normalise(score.mods as u32, mode) {
+            Ok(normalised) => {
+                score.mods = normalised.mods as i32;
+                Some(score)
+            }
+            Err(e) => {
+                log::warn!(
+                    score_id = score.id,
+                    beatmap_md5 = score.beatmap_md5.as_str(),
+                    mods = score.mods,
+                    error = e.to_string();
+                    "Skipping score with invalid mods",
+                );
+
+                None
+            }
+        })
+        .collect()
+}
+
 fn group_scores_by_mods(vec: Vec<LightweightScore>) -> HashMap<i32, Vec<LightweightScore>> {
     let mut grouped_map = HashMap::new();
 
@@ -164,14 +234,35 @@ async fn write_score_pp(
     new_pp: f64,
     scores_table: &str,
     rx: i32,
+    lazer: bool,
     ctx: Arc<Context>,
     run: &RecalculationRun,
 ) -> anyhow::Result<()> {
     let new_pp_for_stats = new_pp as f32;
 
+    // lazer values are kept next to the stable ones, the scores tables only hold stable pp
+    let pp_table = if lazer {
+        "lazer_score_pp"
+    } else {
+        scores_table
+    };
+
     let rows_affected = if run.dry_run {
-        run.record_score_pp(score.id, new_pp_for_stats).await;
+        if !lazer {
+            run.record_score_pp(score.id, new_pp_for_stats).await;
+        }
         0
+    } else if lazer {
+        sqlx::query(
+            "INSERT INTO lazer_score_pp (score_id, rx, pp) VALUES (?, ?, ?)
+            ON DUPLICATE KEY UPDATE pp = VALUES(pp)",
+        )
+        .bind(score.id)
+        .bind(rx)
+        .bind(new_pp)
+        .execute(ctx.database.get().await?.deref_mut())
+        .await?
+        .rows_affected()
     } else {
         sqlx::query(&format!("UPDATE {} SET pp = ? WHERE id = ?", scores_table))
             .bind(new_pp)
@@ -190,6 +281,8 @@ async fn write_score_pp(
         mode = score.play_mode,
         rx = rx,
         mods = score.mods,
+        lazer = lazer,
+        pp_table = pp_table,
         old_pp = score.pp,
         new_pp = new_pp,
         changed = score.pp != new_pp_for_stats,
@@ -211,85 +304,183 @@ async fn recalculate_relax_scores(
     rx: i32,
     run: &RecalculationRun,
 ) -> anyhow::Result<()> {
-    let difficulty_attributes =
-        akatsuki_pp_rs::osu_2019::stars::stars(&beatmap, (mods as u32).into(), None);
+    let stored_key = DifficultyAttributesKey {
+        calculator: "relax".to_string(),
+        version: LIVE_CALCULATOR_VERSION,
+        beatmap_md5: scores[0].beatmap_md5.clone(),
+        mode: scores[0].play_mode,
+        mods: mods as u32,
+    };
+    let difficulty_attributes: OsuDifficultyAttributes =
+        match usecases::difficulty_attributes::fetch(&stored_key, ctx.clone()).await {
+            Some(StoredDifficultyAttributes::Osu(attributes)) => attributes.into(),
+            _ => {
+                let attributes =
+                    akatsuki_pp_rs::osu_2019::stars::stars(beatmap, (mods as u32).into(), None);
+                let stored = StoredDifficultyAttributes::Osu((&attributes).into());
+                usecases::difficulty_attributes::store(&stored_key, &stored, ctx.clone()).await;
+
+                attributes
+            }
+        };
 
     for score in scores {
-        let result =
+        let mut calculate =
             akatsuki_pp_rs::osu_2019::OsuPP::from_attributes(difficulty_attributes.clone())
                 .mods(score.mods as u32)
                 .combo(score.max_combo as u32)
-                .misses(score.count_misses as u32)
-                .accuracy(score.accuracy)
-                .calculate();
+                .misses(score.count_misses as u32);
+
+        calculate = if score.has_hit_results() {
+            calculate
+                .n300(score.count_300 as u32)
+                .n100(score.count_100 as u32)
+                .n50(score.count_50 as u32)
+        } else {
+            calculate.accuracy(score.accuracy)
+        };
+
+        let result = calculate.calculate();
 
         let mut pp = round(result.pp as f32, 2);
         if pp.is_infinite() || pp.is_nan() {
             pp = 0.0;
         }
 
-        write_score_pp(&score, pp as f64, scores_table, rx, ctx.clone(), run).await?;
+        write_score_pp(&score, pp as f64, scores_table, rx, false, ctx.clone(), run).await?;
     }
 
     Ok(())
 }
 
+fn with_score_statistics<'map>(
+    calculate: Performance<'map>,
+    score: &LightweightScore,
+) -> Performance<'map> {
+    let calculate = calculate
+        .combo(score.max_combo as u32)
+        .misses(score.count_misses as u32);
+
+    // mania and catch need the full judgement spread, accuracy alone is ambiguous
+    if score.has_hit_results() {
+        calculate
+            .n300(score.count_300 as u32)
+            .n100(score.count_100 as u32)
+            .n50(score.count_50 as u32)
+            .n_geki(score.count_gekis as u32)
+            .n_katu(score.count_katus as u32)
+    } else {
+        calculate.accuracy(score.accuracy as f64)
+    }
+}
+
+/// Which calculation the scores of a (mode, rx) scope go through.
+#[derive(Clone, Copy, PartialEq)]
+enum ScoreCalculator {
+    Relax,
+    Autopilot,
+    Standard,
+}
+
+impl ScoreCalculator {
+    fn for_scope(mode: i32, rx: i32) -> anyhow::Result<Self> {
+        match (mode, rx) {
+            (0, 1) => Ok(Self::Relax),
+            (0, 2) => Ok(Self::Autopilot),
+            (0..=3, 0) | (1 | 2, 1) => Ok(Self::Standard),
+            _ => Err(anyhow!(
+                "there is no calculator for mode {} with relax bit {}",
+                mode,
+                rx
+            )),
+        }
+    }
+}
+
+fn score_pp(result: &PerformanceAttributes, calculator: ScoreCalculator) -> f64 {
+    match result {
+        PerformanceAttributes::Osu(result) if calculator == ScoreCalculator::Autopilot => {
+            usecases::performance::autopilot_pp(result)
+        }
+        _ => result.pp(),
+    }
+}
+
+/// The pp as written to the scores tables: rounded to two decimals, with broken
+/// calculations stored as 0 rather than NaN or infinity.
+fn stored_score_pp(result: &PerformanceAttributes, calculator: ScoreCalculator) -> f64 {
+    let pp = round(score_pp(result, calculator) as f32, 2);
+    if pp.is_infinite() || pp.is_nan() {
+        return 0.0;
+    }
+
+    pp as f64
+}
+
 async fn recalculate_scores(
     mut scores: Vec<LightweightScore>,
     scores_table: &str,
     beatmap: &Beatmap,
+    calculator: ScoreCalculator,
     ctx: Arc<Context>,
     rx: i32,
     run: &RecalculationRun,
 ) -> anyhow::Result<()> {
     let first_score = scores[0].clone();
+    let mode = Mode::try_from(first_score.play_mode)?;
+
+    let stored_key = DifficultyAttributesKey {
+        calculator: if run.lazer { "live-lazer" } else { "live" }.to_string(),
+        version: LIVE_CALCULATOR_VERSION,
+        beatmap_md5: first_score.beatmap_md5.clone(),
+        mode: first_score.play_mode,
+        mods: first_score.mods as u32,
+    };
 
-    let result = beatmap
-        .performance()
-        .try_mode(match first_score.play_mode {
-            0 => GameMode::Osu,
-            1 => GameMode::Taiko,
-            2 => GameMode::Catch,
-            3 => GameMode::Mania,
-            _ => unreachable!(),
-        })
-        .map_err(|_| {
-            anyhow!(
-                "failed to set mode {} for beatmap {}",
-                first_score.play_mode,
-                first_score.beatmap_id
-            )
-        })?
-        .mods(first_score.mods as u32)
-        .lazer(false)
-        .combo(first_score.max_combo as u32)
-        .misses(first_score.count_misses as u32)
-        .accuracy(first_score.accuracy as f64)
-        .calculate();
-
-    write_score_pp(
-        &first_score,
-        result.pp(),
-        scores_table,
-        rx,
-        ctx.clone(),
-        run,
-    )
-    .await?;
+    // without stored attributes, the first score's calculation works them out
+    let (difficulty_attributes, remaining_scores) =
+        match usecases::difficulty_attributes::fetch(&stored_key, ctx.clone()).await {
+            Some(attributes) => (DifficultyAttributes::from(attributes), &mut scores[..]),
+            None => {
+                let calculate = beatmap
+                    .performance()
+                    .try_mode(mode.game_mode())
+                    .map_err(|_| IncompatibleConvert {
+                        beatmap_mode: Mode::from(beatmap.mode),
+                        mode,
+                    })?
+                    .mods(first_score.mods as u32)
+                    .lazer(run.lazer);
+                let result = with_score_statistics(calculate, &first_score).calculate();
+
+                write_score_pp(
+                    &first_score,
+                    stored_score_pp(&result, calculator),
+                    scores_table,
+                    rx,
+                    run.lazer,
+                    ctx.clone(),
+                    run,
+                )
+                .await?;
 
-    let difficulty_attributes = result.difficulty_attributes();
+                let difficulty_attributes = result.difficulty_attributes();
+                usecases::difficulty_attributes::store(
+                    &stored_key,
+                    &StoredDifficultyAttributes::from(&difficulty_attributes),
+                    ctx.clone(),
+                )
+                .await;
 
-    for score in &mut scores[1..] {
-        let result = difficulty_attributes
+                (difficulty_attributes, &mut scores[1..])
+            }
+        };
+
+    for score in remaining_scores {
+        let calculate = difficulty_attributes
             .clone()
             .performance()
-            .try_mode(match score.play_mode {
-                0 => GameMode::Osu,
-                1 => GameMode::Taiko,
-                2 => GameMode::Catch,
-                3 => GameMode::Mania,
-                _ => unreachable!(),
-            })
+            .try_mode(Mode::try_from(score.play_mode)?.game_mode())
             .map_err(|_| {
                 anyhow!(
                     "failed to set mode {} for beatmap {}",
@@ -298,36 +489,72 @@ async fn recalculate_scores(
                 )
             })?
             .mods(score.mods as u32)
-            .lazer(false)
-            .combo(score.max_combo as u32)
-            .misses(score.count_misses as u32)
-            .accuracy(score.accuracy as f64)
-            .calculate();
+            .lazer(run.lazer);
+        let result = with_score_statistics(calculate, score).calculate();
 
-        let mut pp = round(result.pp() as f32, 2);
-        if pp.is_infinite() || pp.is_nan() {
-            pp = 0.0;
-        }
-
-        write_score_pp(score, pp as f64, scores_table, rx, ctx.clone(), run).await?;
+        write_score_pp(
+            score,
+            stored_score_pp(&result, calculator),
+            scores_table,
+            rx,
+            run.lazer,
+            ctx.clone(),
+            run,
+        )
+        .await?;
     }
 
     Ok(())
 }
 
+/// A map written by the prefetch phase, as long as the file is still the version the
+/// scores were set on. Anything else falls back to the beatmap source.
+async fn load_prefetched_beatmap(
+    prefetch_dir: &Path,
+    beatmap_id: i32,
+    beatmap_md5: &str,
+    ctx: &Context,
+) -> Option<Arc<CachedBeatmap>> {
+    if let Some(beatmap) = ctx.beatmap_cache.get(beatmap_md5) {
+        return Some(beatmap);
+    }
+
+    let path = prefetch_dir.join(format!("{}.osu", beatmap_id));
+    let bytes = tokio::fs::read(&path).await.ok()?;
+    let md5 = usecases::beatmaps::beatmap_md5(&bytes);
+    if usecases::beatmaps::verify_beatmap_md5(beatmap_id, &md5, beatmap_md5).is_err() {
+        log::warn!(
+            beatmap_id = beatmap_id,
+            beatmap_md5 = beatmap_md5,
+            path = path.display().to_string();
+            "Prefetched beatmap file is a different version, fetching it again",
+        );
+        return None;
+    }
+
+    let beatmap = Beatmap::from_bytes(&bytes).ok()?;
+    Some(ctx.beatmap_cache.insert(CachedBeatmap {
+        beatmap_id,
+        md5,
+        bytes,
+        beatmap,
+    }))
+}
+
 async fn recalculate_beatmap(
     beatmap_md5: String,
     scores_table: &str,
     filters: DeployFilters,
     mode: i32,
     rx: i32,
+    prefetch_dir: Option<&Path>,
     ctx: Arc<Context>,
     run: RecalculationRun,
 ) -> anyhow::Result<()> {
     let score_conditions = filters.score_conditions(Some("s"));
 
     let scores: Vec<LightweightScore> = sqlx::query_as(&format!(
-        "SELECT s.id, s.userid, s.beatmap_md5, s.mods, s.max_combo, s.play_mode, b.beatmap_id, s.pp, s.accuracy, s.completed, s.misses_count
+        "SELECT s.id, s.userid, s.beatmap_md5, s.mods, s.max_combo, s.play_mode, b.beatmap_id, s.pp, s.accuracy, s.completed, s.300_count, s.100_count, s.50_count, s.katus_count, s.gekis_count, s.misses_count
         FROM {} s
         INNER JOIN
             beatmaps b
@@ -345,6 +572,7 @@ async fn recalculate_beatmap(
     .fetch_all(ctx.database.get().await?.deref_mut())
     .await?;
 
+    let scores = normalise_score_mods(scores, Mode::try_from(mode)?);
     if scores.is_empty() {
         return Ok(());
     }
@@ -354,37 +582,72 @@ async fn recalculate_beatmap(
 
     let grouped_scores = group_scores_by_mods(scores);
 
-    let beatmap_bytes =
-        usecases::beatmaps::fetch_beatmap_osu_file(base_score.beatmap_id, ctx.clone())
-            .await
-            .with_context(|| {
-                format!(
-                    "failed to fetch .osu for beatmap_id={} beatmap_md5={}",
-                    base_score.beatmap_id, base_score.beatmap_md5
-                )
-            })?;
-
-    let beatmap = Beatmap::from_bytes(&beatmap_bytes).with_context(|| {
-        format!(
-            "failed to parse .osu for beatmap_id={} beatmap_md5={}",
-            base_score.beatmap_id, base_score.beatmap_md5
+    let prefetched = match prefetch_dir {
+        Some(prefetch_dir) => {
+            load_prefetched_beatmap(prefetch_dir, base_score.beatmap_id, &beatmap_md5, &ctx).await
+        }
+        None => None,
+    };
+    let cached_beatmap = match prefetched {
+        Some(cached_beatmap) => cached_beatmap,
+        None => usecases::beatmaps::fetch_verified_beatmap(
+            base_score.beatmap_id,
+            &beatmap_md5,
+            ctx.clone(),
         )
-    })?;
+        .await
+        .with_context(|| {
+            format!(
+                "failed to load .osu for beatmap_id={} beatmap_md5={}",
+                base_score.beatmap_id, base_score.beatmap_md5
+            )
+        })?,
+    };
+    let beatmap = &cached_beatmap.beatmap;
+
+    // scores can only be set on converts of std maps, anything else is bad data
+    // and should not stop the rest of the run
+    let is_convert = match usecases::beatmaps::check_convert(beatmap, Mode::try_from(mode)?) {
+        Ok(is_convert) => is_convert,
+        Err(e) => {
+            log::warn!(
+                beatmap_id = base_score.beatmap_id,
+                beatmap_md5 = beatmap_md5,
+                score_count = score_count,
+                mode = mode,
+                rx = rx,
+                error = e.to_string();
+                "Skipping beatmap that cannot be converted",
+            );
 
+            return Ok(());
+        }
+    };
+
+    let calculator = ScoreCalculator::for_scope(mode, rx)?;
     for (mods, mod_scores) in grouped_scores {
-        if mode == 0 && rx == 1 {
+        if calculator == ScoreCalculator::Relax {
             recalculate_relax_scores(
                 mod_scores,
                 mods,
                 scores_table,
-                &beatmap,
+                beatmap,
                 ctx.clone(),
                 rx,
                 &run,
             )
             .await?;
         } else {
-            recalculate_scores(mod_scores, scores_table, &beatmap, ctx.clone(), rx, &run).await?;
+            recalculate_scores(
+                mod_scores,
+                scores_table,
+                beatmap,
+                calculator,
+                ctx.clone(),
+                rx,
+                &run,
+            )
+            .await?;
         }
     }
 
@@ -393,6 +656,7 @@ async fn recalculate_beatmap(
         score_count = score_count,
         mode = mode,
         rx = rx,
+        is_convert = is_convert,
         dry_run = run.dry_run;
         "Recalculated beatmap"
     );
@@ -400,19 +664,210 @@ async fn recalculate_beatmap(
     Ok(())
 }
 
+#[derive(Clone)]
+struct BeatmapPrefetch {
+    concurrency: usize,
+    output_dir: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum PrefetchFailure {
+    Missing,
+    Outdated,
+    Unparseable,
+    Failed,
+}
+
+impl PrefetchFailure {
+    fn from_error(error: &anyhow::Error) -> Self {
+        if error.downcast_ref::<UnparseableBeatmap>().is_some() {
+            Self::Unparseable
+        } else if error.downcast_ref::<BeatmapMd5Mismatch>().is_some() {
+            Self::Outdated
+        } else if usecases::beatmaps::is_beatmap_missing(error) {
+            Self::Missing
+        } else {
+            Self::Failed
+        }
+    }
+
+    /// Missing, outdated and unparseable beatmaps would fail the score phase again, so
+    /// they are left out of it. Failed fetches get another chance there.
+    fn is_final(self) -> bool {
+        self != Self::Failed
+    }
+}
+
+async fn prefetch_beatmap(
+    beatmap_md5: &str,
+    prefetch: &BeatmapPrefetch,
+    ctx: Arc<Context>,
+) -> Result<(), PrefetchFailure> {
+    let beatmap = match usecases::beatmaps::fetch_by_md5(beatmap_md5, ctx.clone()).await {
+        Ok(Some(beatmap)) => beatmap,
+        Ok(None) => {
+            log::warn!(
+                beatmap_md5 = beatmap_md5;
+                "Prefetch found no beatmap row for md5, its scores will not be recalculated",
+            );
+
+            return Err(PrefetchFailure::Missing);
+        }
+        Err(e) => {
+            log::warn!(
+                beatmap_md5 = beatmap_md5,
+                error = e.to_string();
+                "Prefetch failed to look up beatmap",
+            );
+
+            return Err(PrefetchFailure::Failed);
+        }
+    };
+
+    let result = async {
+        let cached_beatmap = usecases::beatmaps::retry_while_source_unavailable(&ctx, || {
+            usecases::beatmaps::fetch_verified_beatmap(beatmap.beatmap_id, beatmap_md5, ctx.clone())
+        })
+        .await?;
+
+        if let Some(output_dir) = &prefetch.output_dir {
+            let path = output_dir.join(format!("{}.osu", beatmap.beatmap_id));
+            tokio::fs::write(&path, &cached_beatmap.bytes)
+                .await
+                .with_context(|| format!("failed to write {}", path.display()))?;
+        }
+
+        Ok::<(), anyhow::Error>(())
+    }
+    .await;
+
+    result.map_err(|e| {
+        let failure = PrefetchFailure::from_error(&e);
+        log::warn!(
+            beatmap_id = beatmap.beatmap_id,
+            beatmap_md5 = beatmap_md5,
+            retried_later = !failure.is_final(),
+            error = format!("{:#}", e);
+            "Prefetching beatmap failed",
+        );
+
+        failure
+    })
+}
+
+/// Fetches and verifies every beatmap of the run before any score is touched, returning
+/// the md5s of the beatmaps whose scores cannot be recalculated.
+async fn prefetch_beatmaps(
+    beatmap_md5s: &[String],
+    mode: i32,
+    rx: i32,
+    prefetch: &BeatmapPrefetch,
+    ctx: Arc<Context>,
+) -> anyhow::Result<HashSet<String>> {
+    let cache_stats = ctx.beatmap_cache.stats();
+    if prefetch.output_dir.is_none() && beatmap_md5s.len() > cache_stats.max_entries {
+        log::warn!(
+            beatmaps = beatmap_md5s.len(),
+            beatmap_cache_max_entries = cache_stats.max_entries;
+            "Beatmap cache cannot hold every prefetched beatmap, set DEPLOY_PREFETCH_DIR to keep them on disk",
+        );
+    }
+
+    if let Some(output_dir) = &prefetch.output_dir {
+        tokio::fs::create_dir_all(output_dir)
+            .await
+            .with_context(|| format!("failed to create {}", output_dir.display()))?;
+    }
+
+    log::info!(
+        beatmaps = beatmap_md5s.len(),
+        mode = mode,
+        rx = rx,
+        concurrency = prefetch.concurrency;
+        "Starting beatmap prefetch"
+    );
+
+    let semaphore = Arc::new(Semaphore::new(prefetch.concurrency));
+    let mut futures = FuturesUnordered::new();
+
+    for beatmap_md5 in beatmap_md5s {
+        let beatmap_md5 = beatmap_md5.clone();
+        let prefetch = prefetch.clone();
+        let ctx = ctx.clone();
+
+        let permit = semaphore.clone().acquire_owned().await?;
+
+        futures.push(tokio::spawn(async move {
+            let result = prefetch_beatmap(&beatmap_md5, &prefetch, ctx).await;
+            drop(permit);
+
+            (beatmap_md5, result)
+        }));
+    }
+
+    let mut done = 0;
+    let mut fetched = 0;
+    let mut failures = HashMap::new();
+    let mut unusable_md5s = HashSet::new();
+
+    while let Some(result) = futures.next().await {
+        done += 1;
+        if done % 1000 == 0 || done == beatmap_md5s.len() {
+            log::info!(
+                beatmaps_left = beatmap_md5s.len() - done,
+                mode = mode,
+                rx = rx;
+                "Beatmap prefetch progress",
+            );
+        }
+
+        let (beatmap_md5, result) = match result {
+            Ok(result) => result,
+            Err(e) => {
+                log::error!(
+                    error = e.to_string();
+                    "Prefetching beatmap task failed",
+                );
+                continue;
+            }
+        };
+
+        match result {
+            Ok(()) => fetched += 1,
+            Err(failure) => {
+                *failures.entry(failure).or_insert(0) += 1;
+                if failure.is_final() {
+                    unusable_md5s.insert(beatmap_md5);
+                }
+            }
+        }
+    }
+
+    let failures_of = |failure: PrefetchFailure| failures.get(&failure).copied().unwrap_or(0);
+    log::info!(
+        mode = mode,
+        rx = rx,
+        fetched = fetched,
+        missing = failures_of(PrefetchFailure::Missing),
+        outdated = failures_of(PrefetchFailure::Outdated),
+        unparseable = failures_of(PrefetchFailure::Unparseable),
+        failed = failures_of(PrefetchFailure::Failed),
+        skipped = unusable_md5s.len();
+        "Beatmap prefetch finished"
+    );
+
+    Ok(unusable_md5s)
+}
+
 async fn recalculate_mode_scores(
     mode: i32,
     rx: i32,
     ctx: Arc<Context>,
     filters: &DeployFilters,
+    prefetch: Option<&BeatmapPrefetch>,
     run: RecalculationRun,
 ) -> anyhow::Result<()> {
-    let scores_table = match rx {
-        0 => "scores",
-        1 => "scores_relax",
-        2 => "scores_ap",
-        _ => unreachable!(),
-    };
+    let scores_table = RelaxKind::try_from(rx)?.scores_table();
 
     let score_conditions = filters.score_conditions(Some("s"));
 
@@ -453,6 +908,22 @@ async fn recalculate_mode_scores(
         .await?
     };
 
+    let beatmap_md5s = match prefetch {
+        Some(prefetch) => {
+            let md5s = beatmap_md5s
+                .into_iter()
+                .map(|(beatmap_md5,)| beatmap_md5)
+                .collect::<Vec<_>>();
+            let unusable_md5s = prefetch_beatmaps(&md5s, mode, rx, prefetch, ctx.clone()).await?;
+
+            md5s.into_iter()
+                .filter(|beatmap_md5| !unusable_md5s.contains(beatmap_md5))
+                .map(|beatmap_md5| (beatmap_md5,))
+                .collect()
+        }
+        None => beatmap_md5s,
+    };
+
     let semaphore = Arc::new(Semaphore::new(MAX_CONCURRENT_BEATMAP_TASKS));
 
     let mut futures = FuturesUnordered::new();
@@ -461,6 +932,7 @@ async fn recalculate_mode_scores(
         beatmaps = beatmap_md5s.len(),
         mode = mode,
         rx = rx,
+        lazer = run.lazer,
         dry_run = run.dry_run;
         "Starting beatmap recalculation"
     );
@@ -468,25 +940,32 @@ async fn recalculate_mode_scores(
     let mut beatmaps_processed = 0;
     let total_beatmaps = beatmap_md5s.len();
 
+    let prefetch_dir = prefetch.and_then(|prefetch| prefetch.output_dir.clone());
+
     for (beatmap_md5,) in beatmap_md5s {
         let semaphore = semaphore.clone();
         let ctx = ctx.clone();
         let filters = filters.clone();
         let run = run.clone();
         let dry_run = run.dry_run;
+        let prefetch_dir = prefetch_dir.clone();
 
         let permit = semaphore.acquire_owned().await?;
 
         futures.push(tokio::spawn(async move {
-            recalculate_beatmap(
-                beatmap_md5.clone(),
-                scores_table,
-                filters,
-                mode,
-                rx,
-                ctx,
-                run,
-            )
+            // wait out beatmap source outages instead of skipping the beatmap
+            usecases::beatmaps::retry_while_source_unavailable(&ctx, || {
+                recalculate_beatmap(
+                    beatmap_md5.clone(),
+                    scores_table,
+                    filters.clone(),
+                    mode,
+                    rx,
+                    prefetch_dir.as_deref(),
+                    ctx.clone(),
+                    run.clone(),
+                )
+            })
             .await
             .with_context(|| {
                 format!(
@@ -531,10 +1010,14 @@ async fn recalculate_mode_scores(
         }
     }
 
+    let cache_stats = ctx.beatmap_cache.stats();
     log::info!(
         mode = mode,
         rx = rx,
-        dry_run = run.dry_run;
+        dry_run = run.dry_run,
+        beatmap_cache_entries = cache_stats.entries,
+        beatmap_cache_hits = cache_stats.hits,
+        beatmap_cache_misses = cache_stats.misses;
         "Beatmap recalculation finished"
     );
 
@@ -562,12 +1045,7 @@ async fn recalculate_status(
     ctx: Arc<Context>,
     run: &RecalculationRun,
 ) -> anyhow::Result<Vec<(i64, i32)>> {
-    let scores_table = match rx {
-        0 => "scores",
-        1 => "scores_relax",
-        2 => "scores_ap",
-        _ => unreachable!(),
-    };
+    let scores_table = RelaxKind::try_from(rx)?.scores_table();
 
     let mut scores: Vec<ScoreStatus> = sqlx::query_as(
         &format!(
@@ -683,12 +1161,7 @@ async fn recalculate_statuses(
     filters: &DeployFilters,
     run: &RecalculationRun,
 ) -> anyhow::Result<HashMap<i64, i32>> {
-    let scores_table = match rx {
-        0 => "scores",
-        1 => "scores_relax",
-        2 => "scores_ap",
-        _ => unreachable!(),
-    };
+    let scores_table = RelaxKind::try_from(rx)?.scores_table();
 
     let score_conditions = filters.score_conditions(Some("s"));
 
@@ -764,17 +1237,12 @@ async fn recalculate_user(
     let planned_completed =
         recalculate_statuses(user_id, mode, rx, ctx.clone(), filters, run).await?;
 
-    let scores_table = match rx {
-        0 => "scores",
-        1 => "scores_relax",
-        2 => "scores_ap",
-        _ => unreachable!(),
-    };
+    let scores_table = RelaxKind::try_from(rx)?.scores_table();
 
     let (scores, score_count) = if run.dry_run {
         let mut scores: Vec<LightweightScore> = sqlx::query_as(
             &format!(
-                "SELECT s.id, s.userid, s.beatmap_md5, s.mods, s.max_combo, s.play_mode, b.beatmap_id, s.pp, s.accuracy, s.completed, s.misses_count
+                "SELECT s.id, s.userid, s.beatmap_md5, s.mods, s.max_combo, s.play_mode, b.beatmap_id, s.pp, s.accuracy, s.completed, s.300_count, s.100_count, s.50_count, s.katus_count, s.gekis_count, s.misses_count
                 FROM {} s
                 INNER JOIN
                     beatmaps b
@@ -815,7 +1283,7 @@ async fn recalculate_user(
     } else {
         let scores: Vec<LightweightScore> = sqlx::query_as(
             &format!(
-                "SELECT s.id, s.userid, s.beatmap_md5, s.mods, s.max_combo, s.play_mode, b.beatmap_id, s.pp, s.accuracy, s.completed, s.misses_count
+                "SELECT s.id, s.userid, s.beatmap_md5, s.mods, s.max_combo, s.play_mode, b.beatmap_id, s.pp, s.accuracy, s.completed, s.300_count, s.100_count, s.50_count, s.katus_count, s.gekis_count, s.misses_count
                 FROM {} s
                 INNER JOIN
                     beatmaps b
@@ -911,20 +1379,8 @@ async fn recalculate_user(
 
     // unrestricted, and set a score in the past 2 months
     if user_privileges & 1 > 0 && inactive_days < 60 {
-        let redis_leaderboard = match rx {
-            0 => "leaderboard".to_string(),
-            1 => "relaxboard".to_string(),
-            2 => "autoboard".to_string(),
-            _ => unreachable!(),
-        };
-
-        let stats_prefix = match mode {
-            0 => "std",
-            1 => "taiko",
-            2 => "ctb",
-            3 => "mania",
-            _ => unreachable!(),
-        };
+        let redis_leaderboard = RelaxKind::try_from(rx)?.redis_leaderboard();
+        let stats_prefix = Mode::try_from(mode)?.stats_prefix();
 
         let global_key = format!("ripple:{}:{}", redis_leaderboard, stats_prefix);
         let country_key = format!(
@@ -1048,12 +1504,7 @@ async fn find_affected_user_ids(
     ctx: Arc<Context>,
     filters: &DeployFilters,
 ) -> anyhow::Result<Vec<i32>> {
-    let scores_table = match rx {
-        0 => "scores",
-        1 => "scores_relax",
-        2 => "scores_ap",
-        _ => unreachable!(),
-    };
+    let scores_table = RelaxKind::try_from(rx)?.scores_table();
     let score_conditions = filters.score_conditions(Some("s"));
 
     let user_ids: Vec<i32> = if let Some(mapper_filter) = &filters.mapper_filter {
@@ -1103,12 +1554,7 @@ async fn count_matching_scores_and_beatmaps(
     ctx: Arc<Context>,
     filters: &DeployFilters,
 ) -> anyhow::Result<(i64, i64)> {
-    let scores_table = match rx {
-        0 => "scores",
-        1 => "scores_relax",
-        2 => "scores_ap",
-        _ => unreachable!(),
-    };
+    let scores_table = RelaxKind::try_from(rx)?.scores_table();
     let score_conditions = filters.score_conditions(Some("s"));
 
     let counts: (i64, i64) = if let Some(mapper_filter) = &filters.mapper_filter {
@@ -1162,26 +1608,35 @@ async fn count_all_users(ctx: Arc<Context>) -> anyhow::Result<i64> {
         .map_err(Into::into)
 }
 
-fn recalculation_scopes(deploy_args: &DeployArgs) -> Vec<(i32, i32)> {
+/// Every (mode, rx) pair of the requested modes and relax bits, with whether it has a
+/// calculator. Mania has no relax variants, so it always runs as vanilla.
+fn requested_scopes(deploy_args: &DeployArgs) -> Vec<((i32, i32), bool)> {
     let mut scopes = Vec::new();
 
     for mode in &deploy_args.modes {
         let mode = *mode;
-        let rx = vec![0, 1, 2].contains(&mode);
-        let ap = mode == 0;
+        if mode == 3 {
+            scopes.push(((mode, 0), true));
+            continue;
+        }
 
-        if rx || ap {
-            for rx in &deploy_args.relax_bits {
-                scopes.push((mode, *rx));
-            }
-        } else {
-            scopes.push((mode, 0));
+        for rx in &deploy_args.relax_bits {
+            let supported = ScoreCalculator::for_scope(mode, *rx).is_ok();
+            scopes.push(((mode, *rx), supported));
         }
     }
 
     scopes
 }
 
+fn recalculation_scopes(deploy_args: &DeployArgs) -> Vec<(i32, i32)> {
+    requested_scopes(deploy_args)
+        .into_iter()
+        .filter(|(_, supported)| *supported)
+        .map(|(scope, _)| scope)
+        .collect()
+}
+
 async fn preview_recalculation(deploy_args: &DeployArgs, ctx: Arc<Context>) -> anyhow::Result<()> {
     for (mode, rx) in recalculation_scopes(deploy_args) {
         let (matching_scores, matching_beatmaps) =
@@ -1201,6 +1656,7 @@ async fn preview_recalculation(deploy_args: &DeployArgs, ctx: Arc<Context>) -> a
             rx = rx,
             score_recalculation = !deploy_args.total_pp_only,
             total_pp_recalculation = deploy_args.total_pp,
+            lazer = deploy_args.lazer,
             matching_scores = matching_scores,
             matching_beatmaps = matching_beatmaps,
             affected_users = affected_users;
@@ -1256,9 +1712,78 @@ struct DeployArgs {
     total_pp: bool,
     preview: bool,
     dry_run: bool,
+    lazer: bool,
+    prefetch: Option<BeatmapPrefetch>,
     filters: DeployFilters,
 }
 
+impl DeployArgs {
+    fn validate_mod_filters(&self) -> anyhow::Result<()> {
+        let modes = self
+            .modes
+            .iter()
+            .map(|mode| Mode::try_from(*mode))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mod_filters = [
+            ("mods filter", self.filters.mods_filter),
+            ("neq mods filter", self.filters.neq_mods_filter),
+        ];
+        for (name, mods_value) in mod_filters {
+            if let Some(mods_value) = mods_value {
+                mods::validate_filter(mods_value as u32, &modes).with_context(|| {
+                    format!(
+                        "invalid {} {} ({})",
+                        name,
+                        mods_value,
+                        mods::format(mods_value as u32)
+                    )
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn validate_scopes(&self) -> anyhow::Result<()> {
+        let scopes = requested_scopes(self);
+
+        for ((mode, rx), supported) in &scopes {
+            if !supported {
+                log::warn!(
+                    mode = *mode,
+                    rx = *rx;
+                    "Skipping mode and relax bit without a calculator",
+                );
+            }
+        }
+
+        if !scopes.iter().any(|(_, supported)| *supported) {
+            return Err(anyhow!(
+                "none of the requested modes and relax bits has a calculator; autopilot only exists for std, and relax for std, taiko and catch"
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn validate_lazer(&self) -> anyhow::Result<()> {
+        if self.lazer && self.modes.contains(&0) && self.relax_bits.contains(&1) {
+            return Err(anyhow!(
+                "std relax scores have no lazer calculation, leave relax bit 1 out of lazer deploys"
+            ));
+        }
+
+        if self.lazer && (self.total_pp || self.total_pp_only) {
+            return Err(anyhow!(
+                "lazer deploys only write lazer_score_pp, run total pp recalculation separately"
+            ));
+        }
+
+        Ok(())
+    }
+}
+
 fn deploy_after_time_from_env() -> anyhow::Result<Option<i32>> {
     let after_time_str = std::env::var("DEPLOY_AFTER_TIME").ok();
     let after_date_str = std::env::var("DEPLOY_AFTER_DATE").ok();
@@ -1295,6 +1820,54 @@ fn deploy_after_time_from_env() -> anyhow::Result<Option<i32>> {
     Ok(None)
 }
 
+fn parse_modes(modes_str: &str) -> anyhow::Result<Vec<i32>> {
+    modes_str
+        .trim()
+        .split(',')
+        .map(|mode| {
+            let mode = mode
+                .trim()
+                .parse::<i32>()
+                .with_context(|| format!("failed to parse mode {:?}", mode))?;
+            Ok(Mode::try_from(mode)?.as_i32())
+        })
+        .collect()
+}
+
+fn parse_relax_bits(relax_bits_str: &str) -> anyhow::Result<Vec<i32>> {
+    relax_bits_str
+        .trim()
+        .split(',')
+        .map(|rx| {
+            let rx = rx
+                .trim()
+                .parse::<i32>()
+                .with_context(|| format!("failed to parse relax bit {:?}", rx))?;
+            Ok(RelaxKind::try_from(rx)?.as_i32())
+        })
+        .collect()
+}
+
+fn parse_map_filter(map_filter_str: &str) -> anyhow::Result<Vec<i32>> {
+    map_filter_str
+        .trim()
+        .split(',')
+        .map(|beatmap_id| {
+            beatmap_id
+                .trim()
+                .parse::<i32>()
+                .with_context(|| format!("failed to parse map {:?}", beatmap_id))
+        })
+        .collect()
+}
+
+fn parse_mods_filter(mods_str: &str) -> anyhow::Result<i32> {
+    let mods = mods::parse(mods_str)
+        .with_context(|| format!("failed to parse mods {:?}", mods_str.trim()))?;
+
+    Ok(mods as i32)
+}
+
 fn deploy_args_from_env() -> anyhow::Result<DeployArgs> {
     let modes_str = std::env::var("DEPLOY_MODES")?;
     let relax_bits_str = std::env::var("DEPLOY_RELAX_BITS")?;
@@ -1310,6 +1883,11 @@ fn deploy_args_from_env() -> anyhow::Result<DeployArgs> {
         .to_lowercase()
         .trim()
         == "1";
+    let lazer = std::env::var("DEPLOY_LAZER")
+        .unwrap_or_default()
+        .to_lowercase()
+        .trim()
+        == "1";
     let mods_filter_str = std::env::var("DEPLOY_MODS_FILTER").ok();
     let neq_mods_filter_str = std::env::var("DEPLOY_NEQ_MODS_FILTER").ok();
     let mapper_filter_str = std::env::var("DEPLOY_MAPPER_FILTER").ok();
@@ -1319,6 +1897,25 @@ fn deploy_args_from_env() -> anyhow::Result<DeployArgs> {
         .to_lowercase()
         .trim()
         == "1";
+    let prefetch = std::env::var("DEPLOY_PREFETCH")
+        .unwrap_or_default()
+        .to_lowercase()
+        .trim()
+        == "1";
+    let prefetch_concurrency = std::env::var("DEPLOY_PREFETCH_CONCURRENCY")
+        .ok()
+        .map(|concurrency| {
+            concurrency
+                .trim()
+                .parse::<usize>()
+                .with_context(|| format!("invalid DEPLOY_PREFETCH_CONCURRENCY {:?}", concurrency))
+        })
+        .transpose()?
+        .unwrap_or(DEFAULT_PREFETCH_CONCURRENCY);
+    let prefetch_dir = std::env::var("DEPLOY_PREFETCH_DIR")
+        .ok()
+        .filter(|dir| !dir.trim().is_empty())
+        .map(PathBuf::from);
     let after_time = deploy_after_time_from_env()?;
 
     if preview && dry_run {
@@ -1327,34 +1924,33 @@ fn deploy_args_from_env() -> anyhow::Result<DeployArgs> {
         ));
     }
 
+    if prefetch_concurrency == 0 {
+        return Err(anyhow!("DEPLOY_PREFETCH_CONCURRENCY must be at least 1"));
+    }
+
     Ok(DeployArgs {
-        modes: modes_str
-            .trim()
-            .split(',')
-            .map(|s| s.parse::<i32>().expect("failed to parse mode"))
-            .collect::<Vec<_>>(),
-        relax_bits: relax_bits_str
-            .trim()
-            .split(',')
-            .map(|s| s.parse::<i32>().expect("failed to parse relax bits"))
-            .collect::<Vec<_>>(),
+        modes: parse_modes(&modes_str)?,
+        relax_bits: parse_relax_bits(&relax_bits_str)?,
         total_pp_only: total_pp_only_str.to_lowercase().trim() == "1",
         total_pp: total_pp_str.to_lowercase().trim() == "1",
         preview,
         dry_run,
+        lazer,
+        prefetch: prefetch.then(|| BeatmapPrefetch {
+            concurrency: prefetch_concurrency,
+            output_dir: prefetch_dir,
+        }),
         filters: DeployFilters {
             mods_filter: mods_filter_str
-                .map(|mods| mods.trim().parse::<i32>().expect("failed to parse mods")),
+                .map(|mods| parse_mods_filter(&mods))
+                .transpose()?,
             neq_mods_filter: neq_mods_filter_str
-                .map(|mods| mods.trim().parse::<i32>().expect("failed to parse mods")),
+                .map(|mods| parse_mods_filter(&mods))
+                .transpose()?,
             mapper_filter: mapper_filter_str,
-            map_filter: map_filter_str.map(|map_filter| {
-                map_filter
-                    .trim()
-                    .split(',')
-                    .map(|map_filter| map_filter.parse::<i32>().expect("failed to parse map"))
-                    .collect::<Vec<_>>()
-            }),
+            map_filter: map_filter_str
+                .map(|map_filter| parse_map_filter(&map_filter))
+                .transpose()?,
             pp_zero,
             after_time,
         },
@@ -1367,11 +1963,7 @@ fn deploy_args_from_input() -> anyhow::Result<DeployArgs> {
 
     let mut modes_str = String::new();
     std::io::stdin().read_line(&mut modes_str)?;
-    let modes = modes_str
-        .trim()
-        .split(',')
-        .map(|s| s.parse::<i32>().expect("failed to parse mode"))
-        .collect::<Vec<_>>();
+    let modes = parse_modes(&modes_str)?;
 
     print!("\n");
     std::io::stdout().flush()?;
@@ -1381,11 +1973,7 @@ fn deploy_args_from_input() -> anyhow::Result<DeployArgs> {
 
     let mut relax_str = String::new();
     std::io::stdin().read_line(&mut relax_str)?;
-    let relax_bits = relax_str
-        .trim()
-        .split(',')
-        .map(|s| s.parse::<i32>().expect("failed to parse relax bits"))
-        .collect::<Vec<_>>();
+    let relax_bits = parse_relax_bits(&relax_str)?;
 
     print!("\n");
     std::io::stdout().flush()?;
@@ -1410,6 +1998,26 @@ fn deploy_args_from_input() -> anyhow::Result<DeployArgs> {
     print!("\n");
     std::io::stdout().flush()?;
 
+    print!("Lazer calculation (y/n): ");
+    std::io::stdout().flush()?;
+
+    let mut lazer_str = String::new();
+    std::io::stdin().read_line(&mut lazer_str)?;
+    let lazer = lazer_str.to_lowercase().trim() == "y";
+
+    print!("\n");
+    std::io::stdout().flush()?;
+
+    print!("Prefetch beatmaps before recalculating (y/n): ");
+    std::io::stdout().flush()?;
+
+    let mut prefetch_str = String::new();
+    std::io::stdin().read_line(&mut prefetch_str)?;
+    let prefetch = prefetch_str.to_lowercase().trim() == "y";
+
+    print!("\n");
+    std::io::stdout().flush()?;
+
     print!("Mod value recalc only (y/n): ");
     std::io::stdout().flush()?;
 
@@ -1422,17 +2030,12 @@ fn deploy_args_from_input() -> anyhow::Result<DeployArgs> {
 
     let mut mods_value: Option<i32> = None;
     if mod_recalc_value_only {
-        print!("Mods value (int): ");
+        print!("Mods (e.g. HDDT or 72): ");
         std::io::stdout().flush()?;
 
         let mut mods_value_str = String::new();
         std::io::stdin().read_line(&mut mods_value_str)?;
-        mods_value = Some(
-            mods_value_str
-                .trim()
-                .parse::<i32>()
-                .expect("failed to parse mods"),
-        );
+        mods_value = Some(parse_mods_filter(&mods_value_str)?);
 
         print!("\n");
         std::io::stdout().flush()?;
@@ -1450,17 +2053,12 @@ fn deploy_args_from_input() -> anyhow::Result<DeployArgs> {
 
     let mut neq_mods_value: Option<i32> = None;
     if neq_mod_recalc_value_only {
-        print!("Neq mods value (int): ");
+        print!("Neq mods (e.g. HDDTNC or 584): ");
         std::io::stdout().flush()?;
 
         let mut neq_mods_value_str = String::new();
         std::io::stdin().read_line(&mut neq_mods_value_str)?;
-        neq_mods_value = Some(
-            neq_mods_value_str
-                .trim()
-                .parse::<i32>()
-                .expect("failed to parse mods"),
-        );
+        neq_mods_value = Some(parse_mods_filter(&neq_mods_value_str)?);
 
         print!("\n");
         std::io::stdout().flush()?;
@@ -1506,13 +2104,7 @@ fn deploy_args_from_input() -> anyhow::Result<DeployArgs> {
 
         let mut map_str = String::new();
         std::io::stdin().read_line(&mut map_str)?;
-        map_filter = Some(
-            map_str
-                .trim()
-                .split(',')
-                .map(|s| s.parse::<i32>().expect("failed to parse map"))
-                .collect::<Vec<_>>(),
-        );
+        map_filter = Some(parse_map_filter(&map_str)?);
 
         print!("\n");
         std::io::stdout().flush()?;
@@ -1525,6 +2117,11 @@ fn deploy_args_from_input() -> anyhow::Result<DeployArgs> {
         total_pp: total,
         preview: false,
         dry_run: false,
+        lazer,
+        prefetch: prefetch.then(|| BeatmapPrefetch {
+            concurrency: DEFAULT_PREFETCH_CONCURRENCY,
+            output_dir: None,
+        }),
         filters: DeployFilters {
             mods_filter: mods_value,
             neq_mods_filter: neq_mods_value,
@@ -1538,11 +2135,16 @@ fn deploy_args_from_input() -> anyhow::Result<DeployArgs> {
 fn retrieve_deploy_args() -> anyhow::Result<DeployArgs> {
     let env_requested = std::env::vars().any(|(key, _)| key.starts_with("DEPLOY_"));
 
-    if env_requested {
-        deploy_args_from_env()
+    let deploy_args = if env_requested {
+        deploy_args_from_env()?
     } else {
-        deploy_args_from_input()
-    }
+        deploy_args_from_input()?
+    };
+
+    deploy_args.validate_mod_filters()?;
+    deploy_args.validate_scopes()?;
+    deploy_args.validate_lazer()?;
+    Ok(deploy_args)
 }
 
 pub async fn serve(context: Context) -> anyhow::Result<()> {
@@ -1550,7 +2152,11 @@ pub async fn serve(context: Context) -> anyhow::Result<()> {
 
     let context_arc = Arc::new(context);
     let mut affected_users_by_scope = HashMap::new();
-    let run = RecalculationRun::new(deploy_args.dry_run, dry_run_tracks_score_pp(&deploy_args));
+    let run = RecalculationRun::new(
+        deploy_args.dry_run,
+        dry_run_tracks_score_pp(&deploy_args),
+        deploy_args.lazer,
+    );
 
     if deploy_args.preview {
         preview_recalculation(&deploy_args, context_arc).await?;
@@ -1564,51 +2170,23 @@ pub async fn serve(context: Context) -> anyhow::Result<()> {
     }
 
     if !deploy_args.total_pp_only {
-        for mode in &deploy_args.modes {
-            let mode = mode.clone();
-
-            let rx = vec![0, 1, 2].contains(&mode);
-            let ap = mode == 0;
-
-            if rx || ap {
-                for rx in &deploy_args.relax_bits {
-                    if deploy_args.filters.score_selection_is_targeted() {
-                        let affected_user_ids = find_affected_user_ids(
-                            mode,
-                            *rx,
-                            context_arc.clone(),
-                            &deploy_args.filters,
-                        )
+        for (mode, rx) in recalculation_scopes(&deploy_args) {
+            if deploy_args.filters.score_selection_is_targeted() {
+                let affected_user_ids =
+                    find_affected_user_ids(mode, rx, context_arc.clone(), &deploy_args.filters)
                         .await?;
-                        affected_users_by_scope.insert((mode, *rx), affected_user_ids);
-                    }
-
-                    recalculate_mode_scores(
-                        mode,
-                        rx.clone(),
-                        context_arc.clone(),
-                        &deploy_args.filters,
-                        run.clone(),
-                    )
-                    .await?;
-                }
-            } else {
-                if deploy_args.filters.score_selection_is_targeted() {
-                    let affected_user_ids =
-                        find_affected_user_ids(mode, 0, context_arc.clone(), &deploy_args.filters)
-                            .await?;
-                    affected_users_by_scope.insert((mode, 0), affected_user_ids);
-                }
-
-                recalculate_mode_scores(
-                    mode,
-                    0,
-                    context_arc.clone(),
-                    &deploy_args.filters,
-                    run.clone(),
-                )
-                .await?;
+                affected_users_by_scope.insert((mode, rx), affected_user_ids);
             }
+
+            recalculate_mode_scores(
+                mode,
+                rx,
+                context_arc.clone(),
+                &deploy_args.filters,
+                deploy_args.prefetch.as_ref(),
+                run.clone(),
+            )
+            .await?;
         }
     }
 
@@ -1619,66 +2197,29 @@ pub async fn serve(context: Context) -> anyhow::Result<()> {
         return Ok(());
     }
 
-    for mode in &deploy_args.modes {
-        let mode = mode.clone();
-
-        let rx = vec![0, 1, 2].contains(&mode);
-        let ap = mode == 0;
-
-        if rx || ap {
-            for rx in &deploy_args.relax_bits {
-                let affected_user_ids = if deploy_args.filters.score_selection_is_targeted() {
-                    if let Some(affected_user_ids) = affected_users_by_scope.get(&(mode, *rx)) {
-                        Some(affected_user_ids.clone())
-                    } else {
-                        Some(
-                            find_affected_user_ids(
-                                mode,
-                                *rx,
-                                context_arc.clone(),
-                                &deploy_args.filters,
-                            )
-                            .await?,
-                        )
-                    }
-                } else {
-                    None
-                };
-
-                recalculate_mode_users(
-                    mode,
-                    rx.clone(),
-                    context_arc.clone(),
-                    &deploy_args.filters,
-                    affected_user_ids,
-                    run.clone(),
+    for (mode, rx) in recalculation_scopes(&deploy_args) {
+        let affected_user_ids = if deploy_args.filters.score_selection_is_targeted() {
+            if let Some(affected_user_ids) = affected_users_by_scope.get(&(mode, rx)) {
+                Some(affected_user_ids.clone())
+            } else {
+                Some(
+                    find_affected_user_ids(mode, rx, context_arc.clone(), &deploy_args.filters)
+                        .await?,
                 )
-                .await?;
             }
         } else {
-            let affected_user_ids = if deploy_args.filters.score_selection_is_targeted() {
-                if let Some(affected_user_ids) = affected_users_by_scope.get(&(mode, 0)) {
-                    Some(affected_user_ids.clone())
-                } else {
-                    Some(
-                        find_affected_user_ids(mode, 0, context_arc.clone(), &deploy_args.filters)
-                            .await?,
-                    )
-                }
-            } else {
-                None
-            };
+            None
+        };
 
-            recalculate_mode_users(
-                mode,
-                0,
-                context_arc.clone(),
-                &deploy_args.filters,
-                affected_user_ids,
-                run.clone(),
-            )
-            .await?;
-        }
+        recalculate_mode_users(
+            mode,
+            rx,
+            context_arc.clone(),
+            &deploy_args.filters,
+            affected_user_ids,
+            run.clone(),
+        )
+        .await?;
     }
 
     if run.dry_run {
@@ -1687,3 +2228,187 @@ pub async fn serve(context: Context) -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deploy_args(modes: Vec<i32>, relax_bits: Vec<i32>) -> DeployArgs {
+        DeployArgs {
+            modes,
+            relax_bits,
+            total_pp_only: false,
+            total_pp: false,
+            preview: false,
+            dry_run: false,
+            lazer: false,
+            prefetch: None,
+            filters: DeployFilters::default(),
+        }
+    }
+
+    #[test]
+    fn for_scope_only_accepts_scopes_with_a_calculator() {
+        assert!(matches!(
+            ScoreCalculator::for_scope(0, 1),
+            Ok(ScoreCalculator::Relax)
+        ));
+        assert!(matches!(
+            ScoreCalculator::for_scope(0, 2),
+            Ok(ScoreCalculator::Autopilot)
+        ));
+        for (mode, rx) in [(0, 0), (1, 0), (1, 1), (2, 0), (2, 1), (3, 0)] {
+            assert!(matches!(
+                ScoreCalculator::for_scope(mode, rx),
+                Ok(ScoreCalculator::Standard)
+            ));
+        }
+
+        for (mode, rx) in [(1, 2), (2, 2), (3, 1), (3, 2), (0, 3), (4, 0)] {
+            assert!(ScoreCalculator::for_scope(mode, rx).is_err());
+        }
+    }
+
+    #[test]
+    fn recalculation_scopes_skip_unsupported_pairs() {
+        let args = deploy_args(vec![0, 1, 3], vec![0, 1, 2]);
+
+        assert_eq!(
+            recalculation_scopes(&args),
+            vec![(0, 0), (0, 1), (0, 2), (1, 0), (1, 1), (3, 0)]
+        );
+        assert!(args.validate_scopes().is_ok());
+    }
+
+    #[test]
+    fn validate_scopes_rejects_runs_without_a_supported_pair() {
+        assert!(deploy_args(vec![1, 2], vec![2]).validate_scopes().is_err());
+        assert!(deploy_args(vec![3], vec![2]).validate_scopes().is_ok());
+    }
+
+    #[test]
+    fn parse_map_filter_reads_beatmap_ids() {
+        assert_eq!(
+            parse_map_filter(" 75, 129891 \n").unwrap(),
+            vec![75, 129891]
+        );
+    }
+
+    #[test]
+    fn parse_map_filter_rejects_bad_ids() {
+        let error = parse_map_filter("75,abc").unwrap_err();
+        assert_eq!(error.to_string(), "failed to parse map \"abc\"");
+
+        assert!(parse_map_filter("").is_err());
+    }
+
+    #[test]
+    fn score_conditions_are_empty_without_filters() {
+        assert_eq!(DeployFilters::default().score_conditions(None), "");
+        assert_eq!(DeployFilters::default().score_conditions(Some("s")), "");
+    }
+
+    #[test]
+    fn score_conditions_match_any_filtered_mod() {
+        let filters = DeployFilters {
+            mods_filter: Some(parse_mods_filter("HDDT").unwrap()),
+            ..Default::default()
+        };
+
+        assert_eq!(filters.score_conditions(None), "AND (mods & 72) > 0");
+        assert_eq!(filters.score_conditions(Some("s")), "AND (s.mods & 72) > 0");
+    }
+
+    #[test]
+    fn score_conditions_exclude_neq_mods() {
+        let filters = DeployFilters {
+            neq_mods_filter: Some(parse_mods_filter("+HR,NC").unwrap()),
+            ..Default::default()
+        };
+
+        assert_eq!(filters.score_conditions(None), "AND (mods & 528) = 0");
+    }
+
+    #[test]
+    fn score_conditions_combine_filters() {
+        let filters = DeployFilters {
+            mods_filter: Some(8),
+            pp_zero: true,
+            after_time: Some(1_700_000_000),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            filters.score_conditions(Some("s")),
+            "AND (s.mods & 8) > 0 AND s.pp = 0 AND s.time >= 1700000000"
+        );
+    }
+
+    #[test]
+    fn status_filters_drop_pp_zero() {
+        let filters = DeployFilters {
+            pp_zero: true,
+            after_time: Some(1),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            filters.status_filters().score_conditions(None),
+            "AND time >= 1"
+        );
+    }
+
+    #[test]
+    fn validate_lazer_rejects_total_pp_recalculation() {
+        let mut args = deploy_args(vec![0], vec![0]);
+        args.lazer = true;
+        assert!(args.validate_lazer().is_ok());
+
+        args.total_pp = true;
+        assert!(args.validate_lazer().is_err());
+
+        args.total_pp = false;
+        args.total_pp_only = true;
+        assert!(args.validate_lazer().is_err());
+    }
+
+    fn score_with_mods(id: i64, mods: u32) -> LightweightScore {
+        LightweightScore {
+            id,
+            user_id: 1000,
+            beatmap_md5: "a5b99395a42bd55bc5eb1d2411cbdf8b".to_string(),
+            mods: mods as i32,
+            max_combo: 100,
+            play_mode: 0,
+            beatmap_id: 75,
+            pp: 100.0,
+            accuracy: 98.0,
+            completed: 3,
+            count_300: 100,
+            count_100: 2,
+            count_50: 0,
+            count_katus: 0,
+            count_gekis: 0,
+            count_misses: 0,
+        }
+    }
+
+    #[test]
+    fn normalise_score_mods_groups_nc_with_dt_and_skips_invalid_mods() {
+        let scores = vec![
+            score_with_mods(1, mods::DOUBLE_TIME),
+            score_with_mods(2, mods::NIGHTCORE | mods::DOUBLE_TIME),
+            score_with_mods(3, mods::RELAX | mods::AUTOPILOT),
+        ];
+
+        let scores = normalise_score_mods(scores, Mode::Std);
+        assert_eq!(
+            scores.iter().map(|score| score.id).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+
+        let grouped = group_scores_by_mods(scores);
+        assert_eq!(grouped.len(), 1);
+        assert_eq!(grouped[&(mods::DOUBLE_TIME as i32)].len(), 2);
+    }
+}
@@ -3,11 +3,19 @@ use deadpool_lapin::{Manager, Pool};
 use lapin::ConnectionProperties;
 use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
 use performance_service::{
-    api, config::Config, context::Context, deploy, individual_recalc, mass_recalc,
-    models::pool::DbPool, processor,
+    api,
+    config::Config,
+    context::Context,
+    deploy, individual_recalc, mass_recalc,
+    models::{
+        beatmap_cache::BeatmapCache, beatmap_source, circuit_breaker::CircuitBreaker, pool::DbPool,
+    },
+    processor,
 };
 use redis::Client;
 use sqlx::{mysql::MySqlConnectOptions, ConnectOptions};
+use std::sync::Arc;
+use std::time::Duration;
 use structured_logger::{async_json::new_writer, Builder};
 
 fn amqp_dsn(username: &str, password: &str, host: &str, port: u16) -> String {
@@ -80,11 +88,30 @@ async fn main() -> anyhow::Result<()> {
     );
     let redis = Client::open(redis_url)?;
 
+    let beatmap_cache = Arc::new(BeatmapCache::new(
+        config.beatmap_cache_max_entries,
+        config.beatmap_cache_max_bytes,
+        Duration::from_secs(config.beatmap_cache_by_id_ttl_secs),
+    ));
+
+    let beatmap_source = beatmap_source::from_config(&config)?;
+    log::info!(
+        beatmap_source = beatmap_source.name();
+        "Reading beatmaps from the configured source",
+    );
+    let beatmap_source_breaker = Arc::new(CircuitBreaker::new(
+        config.beatmap_source_failure_threshold,
+        Duration::from_secs(config.beatmap_source_cooldown_secs),
+    ));
+
     let context = Context {
         config,
         database,
         amqp_channel,
         redis,
+        beatmap_cache,
+        beatmap_source,
+        beatmap_source_breaker,
     };
 
     match context.config.app_component.as_str() {
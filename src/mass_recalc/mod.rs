@@ -13,12 +13,7 @@ use lapin::{options::BasicPublishOptions, BasicProperties};
 use redis::AsyncCommands;
 
 async fn queue_user(user_id: i32, rework: &Rework, context: &Context) -> anyhow::Result<()> {
-    let scores_table = match rework.rx {
-        0 => "scores",
-        1 => "scores_relax",
-        2 => "scores_ap",
-        _ => unreachable!(),
-    };
+    let scores_table = rework.relax_kind()?.scores_table();
 
     let last_score_time: Option<i32> = sqlx::query_scalar(&format!(
         "SELECT max(time) FROM {} INNER JOIN beatmaps USING(beatmap_md5)
@@ -133,6 +128,10 @@ pub async fn serve(context: Context) -> anyhow::Result<()> {
             .await?
             .expect("failed to find rework");
 
+    // refuse a bad rework row before wiping its existing results
+    rework.mode()?;
+    rework.relax_kind()?;
+
     context
         .amqp_channel
         .queue_purge("rework_queue", QueuePurgeOptions::default())
@@ -0,0 +1,330 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use akatsuki_pp_rs::Beatmap;
+
+/// A fetched .osu file along with its parsed beatmap.
+pub struct CachedBeatmap {
+    pub beatmap_id: i32,
+    pub md5: String,
+    pub bytes: Vec<u8>,
+    pub beatmap: Beatmap,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct BeatmapCacheStats {
+    pub entries: usize,
+    pub bytes: usize,
+    pub max_entries: usize,
+    pub max_bytes: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+struct CacheEntry {
+    beatmap: Arc<CachedBeatmap>,
+    last_used: u64,
+    inserted_at: Instant,
+}
+
+#[derive(Default)]
+struct CacheEntries {
+    by_md5: HashMap<String, CacheEntry>,
+    md5_by_id: HashMap<i32, String>,
+    // md5s by their last use, so the least recently used one is the first
+    by_last_used: BTreeMap<u64, String>,
+    bytes: usize,
+    clock: u64,
+}
+
+impl CacheEntries {
+    fn entry(&mut self, md5: &str) -> Option<&mut CacheEntry> {
+        self.clock += 1;
+        let clock = self.clock;
+
+        let entry = self.by_md5.get_mut(md5)?;
+        self.by_last_used.remove(&entry.last_used);
+        self.by_last_used.insert(clock, md5.to_string());
+        entry.last_used = clock;
+
+        Some(entry)
+    }
+
+    fn touch(&mut self, md5: &str) -> Option<Arc<CachedBeatmap>> {
+        self.entry(md5).map(|entry| entry.beatmap.clone())
+    }
+
+    fn remove(&mut self, md5: &str) -> bool {
+        let Some(entry) = self.by_md5.remove(md5) else {
+            return false;
+        };
+
+        self.bytes -= entry.beatmap.bytes.len();
+        self.by_last_used.remove(&entry.last_used);
+        let beatmap_id = entry.beatmap.beatmap_id;
+        if matches!(self.md5_by_id.get(&beatmap_id), Some(cached) if cached == md5) {
+            self.md5_by_id.remove(&beatmap_id);
+        }
+
+        true
+    }
+
+    fn least_recently_used(&self) -> Option<String> {
+        self.by_last_used.values().next().cloned()
+    }
+}
+
+/// Least recently used cache of beatmaps keyed by md5, bounded by entry count and by
+/// the total size of the .osu files. Shared by everything in a process through `Context`.
+pub struct BeatmapCache {
+    max_entries: usize,
+    max_bytes: usize,
+    by_id_ttl: Duration,
+    entries: Mutex<CacheEntries>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl BeatmapCache {
+    pub fn new(max_entries: usize, max_bytes: usize, by_id_ttl: Duration) -> Self {
+        Self {
+            max_entries,
+            max_bytes,
+            by_id_ttl,
+            entries: Mutex::new(CacheEntries::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    // the entries are consistent between statements, so a panic elsewhere does not
+    // leave them broken
+    fn lock_entries(&self) -> MutexGuard<'_, CacheEntries> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn count(&self, beatmap: Option<Arc<CachedBeatmap>>) -> Option<Arc<CachedBeatmap>> {
+        let counter = match beatmap {
+            Some(_) => &self.hits,
+            None => &self.misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+
+        beatmap
+    }
+
+    pub fn get(&self, md5: &str) -> Option<Arc<CachedBeatmap>> {
+        let beatmap = self.lock_entries().touch(&md5.to_ascii_lowercase());
+
+        self.count(beatmap)
+    }
+
+    /// The most recently cached version of a beatmap, as long as it was fetched within
+    /// `by_id_ttl`. It can still be outdated if the map was updated in the meantime.
+    pub fn get_by_id(&self, beatmap_id: i32) -> Option<Arc<CachedBeatmap>> {
+        let by_id_ttl = self.by_id_ttl;
+        let mut entries = self.lock_entries();
+        let beatmap = match entries.md5_by_id.get(&beatmap_id).cloned() {
+            Some(md5) => entries
+                .entry(&md5)
+                .filter(|entry| entry.inserted_at.elapsed() < by_id_ttl)
+                .map(|entry| entry.beatmap.clone()),
+            None => None,
+        };
+        drop(entries);
+
+        self.count(beatmap)
+    }
+
+    /// Caches a beatmap, evicting the least recently used ones to stay within the limits.
+    /// It becomes the version returned by `get_by_id`.
+    pub fn insert(&self, beatmap: CachedBeatmap) -> Arc<CachedBeatmap> {
+        let md5 = beatmap.md5.to_ascii_lowercase();
+        let beatmap = Arc::new(beatmap);
+
+        if self.max_entries == 0 || beatmap.bytes.len() > self.max_bytes {
+            return beatmap;
+        }
+
+        let mut entries = self.lock_entries();
+        entries.remove(&md5);
+
+        while entries.by_md5.len() >= self.max_entries
+            || entries.bytes + beatmap.bytes.len() > self.max_bytes
+        {
+            let Some(evicted) = entries.least_recently_used() else {
+                break;
+            };
+            entries.remove(&evicted);
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+
+        entries.clock += 1;
+        let last_used = entries.clock;
+        entries.bytes += beatmap.bytes.len();
+        entries.md5_by_id.insert(beatmap.beatmap_id, md5.clone());
+        entries.by_last_used.insert(last_used, md5.clone());
+        entries.by_md5.insert(
+            md5,
+            CacheEntry {
+                beatmap: beatmap.clone(),
+                last_used,
+                inserted_at: Instant::now(),
+            },
+        );
+
+        beatmap
+    }
+
+    /// Drops every cached version of a beatmap, e.g. after it was updated.
+    pub fn invalidate_beatmap(&self, beatmap_id: i32) -> usize {
+        let mut entries = self.lock_entries();
+        let md5s: Vec<String> = entries
+            .by_md5
+            .iter()
+            .filter(|(_, entry)| entry.beatmap.beatmap_id == beatmap_id)
+            .map(|(md5, _)| md5.clone())
+            .collect();
+
+        for md5 in &md5s {
+            entries.remove(md5);
+        }
+
+        md5s.len()
+    }
+
+    pub fn stats(&self) -> BeatmapCacheStats {
+        let entries = self.lock_entries();
+
+        BeatmapCacheStats {
+            entries: entries.by_md5.len(),
+            bytes: entries.bytes,
+            max_entries: self.max_entries,
+            max_bytes: self.max_bytes,
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    fn beatmap(beatmap_id: i32, md5: &str, size: usize) -> CachedBeatmap {
+        let mut bytes = b"osu file format v14\n\n[General]\nMode: 0\n".to_vec();
+        bytes.resize(size.max(bytes.len()), b'\n');
+
+        CachedBeatmap {
+            beatmap_id,
+            md5: md5.to_string(),
+            beatmap: Beatmap::from_bytes(&bytes).unwrap(),
+            bytes,
+        }
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_entry() {
+        let cache = BeatmapCache::new(2, usize::MAX, HOUR);
+        cache.insert(beatmap(1, "a", 0));
+        cache.insert(beatmap(2, "b", 0));
+
+        // "a" is now more recent than "b"
+        assert!(cache.get("a").is_some());
+        cache.insert(beatmap(3, "c", 0));
+
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("c").is_some());
+        assert!(cache.get_by_id(2).is_none());
+        assert_eq!(cache.stats().entries, 2);
+        assert_eq!(cache.stats().evictions, 1);
+    }
+
+    #[test]
+    fn evicts_to_stay_within_max_bytes() {
+        let cache = BeatmapCache::new(10, 250, HOUR);
+        cache.insert(beatmap(1, "a", 100));
+        cache.insert(beatmap(2, "b", 100));
+        cache.insert(beatmap(3, "c", 100));
+
+        assert!(cache.get("a").is_none());
+        assert_eq!(cache.stats().entries, 2);
+        assert_eq!(cache.stats().bytes, 200);
+
+        // larger than the whole cache, so it is returned but not kept
+        let large = cache.insert(beatmap(4, "d", 300));
+        assert_eq!(large.beatmap_id, 4);
+        assert!(cache.get("d").is_none());
+        assert_eq!(cache.stats().entries, 2);
+    }
+
+    #[test]
+    fn reinserting_replaces_the_entry() {
+        let cache = BeatmapCache::new(10, usize::MAX, HOUR);
+        cache.insert(beatmap(1, "a", 100));
+        cache.insert(beatmap(1, "A", 150));
+
+        let stats = cache.stats();
+        assert_eq!(stats.entries, 1);
+        assert_eq!(stats.bytes, 150);
+        assert_eq!(stats.evictions, 0);
+    }
+
+    #[test]
+    fn get_by_id_returns_the_latest_version() {
+        let cache = BeatmapCache::new(10, usize::MAX, HOUR);
+        cache.insert(beatmap(1, "old", 0));
+        cache.insert(beatmap(1, "new", 0));
+
+        assert_eq!(cache.get_by_id(1).unwrap().md5, "new");
+        // the old version is still there for lookups by its md5
+        assert!(cache.get("old").is_some());
+    }
+
+    #[test]
+    fn get_by_id_skips_entries_older_than_the_ttl() {
+        let cache = BeatmapCache::new(10, usize::MAX, Duration::ZERO);
+        cache.insert(beatmap(1, "a", 0));
+
+        assert!(cache.get_by_id(1).is_none());
+        assert!(cache.get("a").is_some());
+
+        let stats = cache.stats();
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 1);
+    }
+
+    #[test]
+    fn invalidate_beatmap_drops_every_version() {
+        let cache = BeatmapCache::new(10, usize::MAX, HOUR);
+        cache.insert(beatmap(1, "old", 100));
+        cache.insert(beatmap(1, "new", 100));
+        cache.insert(beatmap(2, "other", 100));
+
+        assert_eq!(cache.invalidate_beatmap(1), 2);
+        assert!(cache.get("old").is_none());
+        assert!(cache.get("new").is_none());
+        assert!(cache.get_by_id(1).is_none());
+        assert!(cache.get("other").is_some());
+        assert_eq!(cache.stats().bytes, 100);
+        assert_eq!(cache.invalidate_beatmap(1), 0);
+    }
+
+    #[test]
+    fn disabled_cache_keeps_nothing() {
+        let cache = BeatmapCache::new(0, usize::MAX, HOUR);
+        cache.insert(beatmap(1, "a", 0));
+
+        assert!(cache.get("a").is_none());
+        assert_eq!(cache.stats().entries, 0);
+    }
+}
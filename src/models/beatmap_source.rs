@@ -0,0 +1,388 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context as _};
+use async_trait::async_trait;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use reqwest::{StatusCode, Url};
+use sha2::{Digest, Sha256};
+
+use crate::config::Config;
+
+/// Where .osu files are read from, chosen by `BEATMAP_SOURCE`.
+#[async_trait]
+pub trait BeatmapSource: Send + Sync {
+    async fn fetch_osu_file(&self, beatmap_id: i32) -> anyhow::Result<Vec<u8>>;
+
+    fn name(&self) -> &'static str;
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum BeatmapSourceKind {
+    Http,
+    S3,
+    Local,
+}
+
+/// Whether a failed fetch could succeed when tried again: timeouts, connection errors,
+/// server errors and rate limits. Missing maps and local file errors are final.
+pub fn is_retryable(error: &anyhow::Error) -> bool {
+    match error.downcast_ref::<reqwest::Error>() {
+        Some(e) => e.status().map_or(true, |status| {
+            status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+        }),
+        None => false,
+    }
+}
+
+pub fn from_config(config: &Config) -> anyhow::Result<Arc<dyn BeatmapSource>> {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(config.beatmap_fetch_timeout_secs))
+        .connect_timeout(Duration::from_secs(
+            config.beatmap_fetch_connect_timeout_secs,
+        ))
+        .build()?;
+
+    let source: Arc<dyn BeatmapSource> = match config.beatmap_source {
+        BeatmapSourceKind::Http => {
+            if config.beatmaps_service_base_url.is_empty() {
+                return Err(anyhow!("BEATMAPS_SERVICE_BASE_URL has no urls"));
+            }
+
+            Arc::new(HttpBeatmapSource {
+                client,
+                base_urls: config.beatmaps_service_base_url.clone(),
+                preferred: AtomicUsize::new(0),
+            })
+        }
+        BeatmapSourceKind::S3 => Arc::new(S3BeatmapSource::new(config, client)?),
+        BeatmapSourceKind::Local => Arc::new(LocalBeatmapSource {
+            directory: config
+                .beatmaps_local_dir
+                .clone()
+                .context("BEATMAPS_LOCAL_DIR is required when BEATMAP_SOURCE=local")?,
+        }),
+    };
+
+    Ok(source)
+}
+
+/// The beatmaps service, which fetches maps from osu! when it does not have them.
+/// With several base urls, requests go to the last one that worked and fail over to
+/// the others in order.
+pub struct HttpBeatmapSource {
+    client: reqwest::Client,
+    base_urls: Vec<String>,
+    preferred: AtomicUsize,
+}
+
+impl HttpBeatmapSource {
+    async fn fetch_from(&self, base_url: &str, beatmap_id: i32) -> anyhow::Result<Vec<u8>> {
+        let url = format!("{}/api/osu-api/v1/osu-files/{}", base_url, beatmap_id);
+        let response = self.client.get(url).send().await?.error_for_status()?;
+
+        Ok(response.bytes().await?.to_vec())
+    }
+}
+
+#[async_trait]
+impl BeatmapSource for HttpBeatmapSource {
+    async fn fetch_osu_file(&self, beatmap_id: i32) -> anyhow::Result<Vec<u8>> {
+        let preferred = self.preferred.load(Ordering::Relaxed);
+        let mut last_error = None;
+
+        for offset in 0..self.base_urls.len() {
+            let idx = (preferred + offset) % self.base_urls.len();
+            let base_url = &self.base_urls[idx];
+
+            match self.fetch_from(base_url, beatmap_id).await {
+                Ok(bytes) => {
+                    if idx != preferred {
+                        self.preferred.store(idx, Ordering::Relaxed);
+                        log::warn!(
+                            base_url = base_url;
+                            "Failed over to another beatmaps service url",
+                        );
+                    }
+
+                    return Ok(bytes);
+                }
+                Err(e) if is_retryable(&e) => {
+                    log::warn!(
+                        beatmap_id = beatmap_id,
+                        base_url = base_url,
+                        error = e.to_string();
+                        "Fetching beatmap from beatmaps service url failed",
+                    );
+                    last_error = Some(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(last_error.expect("there is at least one base url"))
+    }
+
+    fn name(&self) -> &'static str {
+        "http"
+    }
+}
+
+/// A folder of `<beatmap_id>.osu` files.
+pub struct LocalBeatmapSource {
+    directory: PathBuf,
+}
+
+#[async_trait]
+impl BeatmapSource for LocalBeatmapSource {
+    async fn fetch_osu_file(&self, beatmap_id: i32) -> anyhow::Result<Vec<u8>> {
+        let path = self.directory.join(format!("{}.osu", beatmap_id));
+        let bytes = tokio::fs::read(&path)
+            .await
+            .with_context(|| format!("failed to read {}", path.display()))?;
+
+        Ok(bytes)
+    }
+
+    fn name(&self) -> &'static str {
+        "local"
+    }
+}
+
+// everything but the unreserved characters, as required by sigv4
+const S3_KEY_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+const EMPTY_PAYLOAD_SHA256: &str =
+    "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+/// `<prefix><beatmap_id>.osu` objects in an S3-compatible bucket, requested path-style
+/// with sigv4 signed GETs.
+pub struct S3BeatmapSource {
+    client: reqwest::Client,
+    endpoint: Url,
+    host: String,
+    bucket: String,
+    key_prefix: String,
+    region: String,
+    access_key_id: String,
+    secret_access_key: String,
+}
+
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac accepts any key length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// The host header of requests to `endpoint`, which includes a non-default port.
+fn endpoint_host(endpoint: &Url) -> anyhow::Result<String> {
+    match (endpoint.host_str(), endpoint.port()) {
+        (Some(host), Some(port)) => Ok(format!("{}:{}", host, port)),
+        (Some(host), None) => Ok(host.to_string()),
+        (None, _) => Err(anyhow!("AWS_ENDPOINT_URL {} has no host", endpoint)),
+    }
+}
+
+impl S3BeatmapSource {
+    fn new(config: &Config, client: reqwest::Client) -> anyhow::Result<Self> {
+        let endpoint = Url::parse(&config.aws_endpoint_url)
+            .with_context(|| format!("invalid AWS_ENDPOINT_URL {}", config.aws_endpoint_url))?;
+
+        Ok(Self {
+            client,
+            host: endpoint_host(&endpoint)?,
+            endpoint,
+            bucket: config.aws_bucket_name.clone(),
+            key_prefix: config.beatmaps_s3_key_prefix.clone(),
+            region: config.aws_region.clone(),
+            access_key_id: config.aws_access_key_id.clone(),
+            secret_access_key: config.aws_secret_access_key.clone(),
+        })
+    }
+
+    fn object_path(&self, beatmap_id: i32) -> String {
+        let key = format!("{}{}.osu", self.key_prefix, beatmap_id);
+        let base_path = self.endpoint.path().trim_end_matches('/');
+
+        std::iter::once(self.bucket.as_str())
+            .chain(key.split('/'))
+            .map(|segment| utf8_percent_encode(segment, S3_KEY_ENCODE_SET).to_string())
+            .fold(base_path.to_string(), |path, segment| path + "/" + &segment)
+    }
+
+    fn authorization(&self, path: &str, amz_date: &str) -> String {
+        let date = &amz_date[..8];
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+
+        let canonical_request = format!(
+            "GET\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            path, self.host, EMPTY_PAYLOAD_SHA256, amz_date, signed_headers, EMPTY_PAYLOAD_SHA256
+        );
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let date_key = hmac_sha256(format!("AWS4{}", self.secret_access_key).as_bytes(), date);
+        let region_key = hmac_sha256(&date_key, &self.region);
+        let service_key = hmac_sha256(&region_key, "s3");
+        let signing_key = hmac_sha256(&service_key, "aws4_request");
+        let signature = hex::encode(hmac_sha256(&signing_key, &string_to_sign));
+
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.access_key_id, scope, signed_headers, signature
+        )
+    }
+}
+
+#[async_trait]
+impl BeatmapSource for S3BeatmapSource {
+    async fn fetch_osu_file(&self, beatmap_id: i32) -> anyhow::Result<Vec<u8>> {
+        let path = self.object_path(beatmap_id);
+        let mut url = self.endpoint.clone();
+        url.set_path(&path);
+        let amz_date = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+
+        let response = self
+            .client
+            .get(url)
+            .header("x-amz-date", &amz_date)
+            .header("x-amz-content-sha256", EMPTY_PAYLOAD_SHA256)
+            .header(
+                reqwest::header::AUTHORIZATION,
+                self.authorization(&path, &amz_date),
+            )
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(response.bytes().await?.to_vec())
+    }
+
+    fn name(&self) -> &'static str {
+        "s3"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status_error(status: u16) -> anyhow::Error {
+        let response = axum::http::Response::builder()
+            .status(status)
+            .body("")
+            .unwrap();
+
+        reqwest::Response::from(response)
+            .error_for_status()
+            .unwrap_err()
+            .into()
+    }
+
+    #[test]
+    fn server_errors_and_rate_limits_are_retryable() {
+        assert!(is_retryable(&status_error(500)));
+        assert!(is_retryable(&status_error(502)));
+        assert!(is_retryable(&status_error(503)));
+        assert!(is_retryable(&status_error(429)));
+    }
+
+    #[test]
+    fn client_errors_are_final() {
+        assert!(!is_retryable(&status_error(404)));
+        assert!(!is_retryable(&status_error(403)));
+        assert!(!is_retryable(&status_error(400)));
+    }
+
+    #[tokio::test]
+    async fn connection_errors_are_retryable() {
+        let error = reqwest::Client::new()
+            .get("http://127.0.0.1:1/api/osu-api/v1/osu-files/75")
+            .send()
+            .await
+            .unwrap_err();
+
+        assert!(is_retryable(&error.into()));
+    }
+
+    #[test]
+    fn local_file_errors_are_final() {
+        let error = std::io::Error::from(std::io::ErrorKind::NotFound);
+        assert!(!is_retryable(&anyhow::Error::new(error)));
+    }
+
+    #[test]
+    fn context_does_not_hide_the_reqwest_error() {
+        let error = status_error(503).context("failed to fetch beatmap 75");
+        assert!(is_retryable(&error));
+    }
+
+    fn s3_source(endpoint: &str, key_prefix: &str) -> S3BeatmapSource {
+        let endpoint = Url::parse(endpoint).unwrap();
+
+        S3BeatmapSource {
+            client: reqwest::Client::new(),
+            host: endpoint_host(&endpoint).unwrap(),
+            endpoint,
+            bucket: "beatmaps".to_string(),
+            key_prefix: key_prefix.to_string(),
+            region: "us-east-1".to_string(),
+            access_key_id: "AKIDEXAMPLE".to_string(),
+            secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+        }
+    }
+
+    #[test]
+    fn endpoint_host_keeps_non_default_ports() {
+        let host = |url: &str| endpoint_host(&Url::parse(url).unwrap()).unwrap();
+
+        assert_eq!(host("http://localhost:9000"), "localhost:9000");
+        assert_eq!(host("https://s3.amazonaws.com"), "s3.amazonaws.com");
+        assert_eq!(host("https://s3.amazonaws.com:443"), "s3.amazonaws.com");
+    }
+
+    #[test]
+    fn object_path_is_path_style_and_encoded() {
+        let source = s3_source("http://localhost:9000", "osu/");
+        assert_eq!(source.object_path(75), "/beatmaps/osu/75.osu");
+
+        let source = s3_source("http://localhost:9000/storage/", "maps v2/");
+        assert_eq!(source.object_path(75), "/storage/beatmaps/maps%20v2/75.osu");
+    }
+
+    // the expected values are what botocore's S3SigV4Auth signs for the same request
+    #[test]
+    fn authorization_matches_the_reference_signature() {
+        let source = s3_source("http://localhost:9000", "osu/");
+
+        assert_eq!(
+            source.authorization("/beatmaps/osu/75.osu", "20240102T030405Z"),
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20240102/us-east-1/s3/aws4_request, \
+             SignedHeaders=host;x-amz-content-sha256;x-amz-date, \
+             Signature=9ae11539f61c496de2adc75cfcd5f5383b2a429412c86adc5e69e6b62f9ceadd"
+        );
+    }
+
+    #[test]
+    fn authorization_signs_the_encoded_path() {
+        let source = s3_source("http://localhost:9000", "maps v2/");
+        let path = source.object_path(75);
+
+        assert!(source.authorization(&path, "20240102T030405Z").ends_with(
+            "Signature=8a2420aac343c2480559846e05dafa046f5f312f5702299ffcad3d7a83b442f0"
+        ));
+    }
+}
@@ -0,0 +1,265 @@
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+/// How often callers waiting on a probe check whether it has finished.
+const PROBE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Default)]
+struct BreakerState {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+    // set while the call let through after the cooldown is in flight; a probe whose
+    // caller went away without reporting back stops counting after another cooldown
+    probe_until: Option<Instant>,
+}
+
+/// Opens after `failure_threshold` consecutive failures and stays open for `cooldown`.
+/// Once the cooldown is over a single probe call is let through, and everything else
+/// stays short-circuited until it succeeds, which closes the breaker, or fails, which
+/// reopens it.
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    cooldown: Duration,
+    state: Mutex<BreakerState>,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            failure_threshold,
+            cooldown,
+            state: Mutex::new(BreakerState::default()),
+        }
+    }
+
+    // the state is replaced as a whole, so a panic while it is locked cannot leave it
+    // half updated
+    fn lock_state(&self) -> MutexGuard<'_, BreakerState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// How long until calls may be let through again, `None` while closed or while the
+    /// cooldown is over and no probe is in flight.
+    pub fn open_for(&self) -> Option<Duration> {
+        let state = self.lock_state();
+
+        Self::short_circuit_for(&state, Instant::now())
+    }
+
+    fn short_circuit_for(state: &BreakerState, now: Instant) -> Option<Duration> {
+        let open_until = state.open_until?;
+        if now < open_until {
+            return Some(open_until - now);
+        }
+
+        state
+            .probe_until
+            .filter(|&probe_until| now < probe_until)
+            .map(|probe_until| PROBE_POLL_INTERVAL.min(probe_until - now))
+    }
+
+    /// Lets a call through, or returns how long until calls may be let through again.
+    /// The first call after the cooldown becomes the probe, and the caller has to report
+    /// how it went with `record_success` or `record_failure`.
+    pub fn try_acquire(&self) -> Result<(), Duration> {
+        let mut state = self.lock_state();
+        let now = Instant::now();
+
+        if let Some(retry_in) = Self::short_circuit_for(&state, now) {
+            return Err(retry_in);
+        }
+
+        if state.open_until.is_some() {
+            state.probe_until = Some(now + self.cooldown);
+        }
+
+        Ok(())
+    }
+
+    pub fn record_success(&self) {
+        let mut state = self.lock_state();
+        let was_open = state.open_until.is_some();
+        *state = BreakerState::default();
+        drop(state);
+
+        if was_open {
+            log::info!("Circuit breaker closed, calls succeed again");
+        }
+    }
+
+    /// Returns whether the breaker is open after this failure.
+    pub fn record_failure(&self) -> bool {
+        let mut state = self.lock_state();
+        state.consecutive_failures += 1;
+
+        if self.failure_threshold == 0 || state.consecutive_failures < self.failure_threshold {
+            return false;
+        }
+
+        state.open_until = Some(Instant::now() + self.cooldown);
+        state.probe_until = None;
+        let consecutive_failures = state.consecutive_failures;
+        drop(state);
+
+        log::warn!(
+            consecutive_failures = consecutive_failures,
+            cooldown_secs = self.cooldown.as_secs();
+            "Circuit breaker opened",
+        );
+
+        true
+    }
+
+    pub async fn wait_until_closed(&self) {
+        while let Some(remaining) = self.open_for() {
+            tokio::time::sleep(remaining).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    #[test]
+    fn opens_after_the_failure_threshold() {
+        let breaker = CircuitBreaker::new(3, HOUR);
+
+        assert!(!breaker.record_failure());
+        assert!(!breaker.record_failure());
+        assert!(breaker.open_for().is_none());
+
+        assert!(breaker.record_failure());
+        let open_for = breaker.open_for().unwrap();
+        assert!(open_for > Duration::from_secs(3590) && open_for <= HOUR);
+    }
+
+    #[test]
+    fn success_resets_the_failure_count() {
+        let breaker = CircuitBreaker::new(2, HOUR);
+
+        assert!(!breaker.record_failure());
+        breaker.record_success();
+        assert!(!breaker.record_failure());
+        assert!(breaker.open_for().is_none());
+    }
+
+    #[test]
+    fn success_closes_an_open_breaker() {
+        let breaker = CircuitBreaker::new(1, HOUR);
+
+        assert!(breaker.record_failure());
+        breaker.record_success();
+        assert!(breaker.open_for().is_none());
+        assert!(breaker.record_failure());
+    }
+
+    #[test]
+    fn failure_after_the_cooldown_reopens_it() {
+        let breaker = CircuitBreaker::new(2, Duration::ZERO);
+
+        assert!(!breaker.record_failure());
+        assert!(breaker.record_failure());
+        // the cooldown is already over
+        assert!(breaker.open_for().is_none());
+        assert!(breaker.record_failure());
+    }
+
+    #[test]
+    fn zero_threshold_never_opens() {
+        let breaker = CircuitBreaker::new(0, HOUR);
+
+        for _ in 0..10 {
+            assert!(!breaker.record_failure());
+        }
+        assert!(breaker.open_for().is_none());
+    }
+
+    #[tokio::test]
+    async fn wait_until_closed_returns_once_the_cooldown_is_over() {
+        let breaker = CircuitBreaker::new(1, Duration::from_millis(20));
+        assert!(breaker.record_failure());
+
+        tokio::time::timeout(Duration::from_secs(5), breaker.wait_until_closed())
+            .await
+            .unwrap();
+        assert!(breaker.open_for().is_none());
+    }
+
+    #[test]
+    fn survives_a_poisoned_lock() {
+        let breaker = std::sync::Arc::new(CircuitBreaker::new(1, HOUR));
+
+        let poisoner = breaker.clone();
+        let _ = std::thread::spawn(move || {
+            let _state = poisoner.state.lock().unwrap();
+            panic!("poison the breaker state");
+        })
+        .join();
+
+        assert!(breaker.state.is_poisoned());
+        assert!(breaker.record_failure());
+        assert!(breaker.open_for().is_some());
+    }
+
+    fn end_cooldown(breaker: &CircuitBreaker) {
+        breaker.lock_state().open_until = Some(Instant::now());
+    }
+
+    #[test]
+    fn lets_a_single_probe_through_after_the_cooldown() {
+        let breaker = CircuitBreaker::new(1, HOUR);
+
+        assert!(breaker.record_failure());
+        assert!(breaker.try_acquire().is_err());
+
+        end_cooldown(&breaker);
+        assert!(breaker.open_for().is_none());
+        assert!(breaker.try_acquire().is_ok());
+
+        // everything else waits for the probe
+        for _ in 0..3 {
+            let retry_in = breaker.try_acquire().unwrap_err();
+            assert!(retry_in <= PROBE_POLL_INTERVAL);
+        }
+        assert!(breaker.open_for().is_some());
+
+        breaker.record_success();
+        assert!(breaker.try_acquire().is_ok());
+        assert!(breaker.try_acquire().is_ok());
+        assert!(breaker.open_for().is_none());
+    }
+
+    #[test]
+    fn failed_probe_reopens_it() {
+        let breaker = CircuitBreaker::new(1, HOUR);
+
+        assert!(breaker.record_failure());
+        end_cooldown(&breaker);
+        assert!(breaker.try_acquire().is_ok());
+
+        assert!(breaker.record_failure());
+        let retry_in = breaker.try_acquire().unwrap_err();
+        assert!(retry_in > Duration::from_secs(3590) && retry_in <= HOUR);
+
+        // and the next cooldown ends with a new probe
+        end_cooldown(&breaker);
+        assert!(breaker.try_acquire().is_ok());
+        assert!(breaker.try_acquire().is_err());
+    }
+
+    #[test]
+    fn abandoned_probe_is_replaced() {
+        let breaker = CircuitBreaker::new(1, HOUR);
+
+        assert!(breaker.record_failure());
+        end_cooldown(&breaker);
+        assert!(breaker.try_acquire().is_ok());
+
+        breaker.lock_state().probe_until = Some(Instant::now());
+        assert!(breaker.try_acquire().is_ok());
+        assert!(breaker.try_acquire().is_err());
+    }
+}
@@ -0,0 +1,226 @@
+use akatsuki_pp_rs::{
+    any::DifficultyAttributes, catch::CatchDifficultyAttributes, mania::ManiaDifficultyAttributes,
+    osu::OsuDifficultyAttributes, taiko::TaikoDifficultyAttributes,
+};
+
+/// The akatsuki-pp revision the live and relax calculators are built from, read from
+/// Cargo.toml by build.rs so attributes from an older calculator are never reused.
+pub const LIVE_CALCULATOR_VERSION: &str = env!("AKATSUKI_PP_REV");
+
+/// Identifies one set of stored difficulty attributes. Only mods are part of it, so
+/// calculations with difficulty overrides, rate edits or passed objects are not stored.
+pub struct DifficultyAttributesKey {
+    pub calculator: String,
+    pub version: &'static str,
+    pub beatmap_md5: String,
+    pub mode: i32,
+    pub mods: u32,
+}
+
+impl DifficultyAttributesKey {
+    pub fn redis_key(&self) -> String {
+        format!(
+            "performance:difficulty:{}:{}:{}:{}:{}",
+            self.calculator,
+            self.version,
+            self.beatmap_md5.to_ascii_lowercase(),
+            self.mode,
+            self.mods
+        )
+    }
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct StoredOsuAttributes {
+    pub aim: f64,
+    pub speed: f64,
+    pub flashlight: f64,
+    pub slider_factor: f64,
+    pub speed_note_count: f64,
+    pub ar: f64,
+    pub od: f64,
+    pub hp: f64,
+    pub n_circles: u32,
+    pub n_sliders: u32,
+    pub n_slider_ticks: u32,
+    pub n_spinners: u32,
+    pub stars: f64,
+    pub max_combo: u32,
+}
+
+/// Conversions for the osu! attributes of every akatsuki-pp revision that can start a
+/// calculation from stored attributes. The struct literals are exhaustive on purpose, a
+/// revision with different fields has to fail to build rather than lose some.
+macro_rules! impl_stored_osu_attributes {
+    ($($attributes:ty),* $(,)?) => {$(
+        impl From<&$attributes> for $crate::models::difficulty_attributes::StoredOsuAttributes {
+            fn from(attributes: &$attributes) -> Self {
+                Self {
+                    aim: attributes.aim,
+                    speed: attributes.speed,
+                    flashlight: attributes.flashlight,
+                    slider_factor: attributes.slider_factor,
+                    speed_note_count: attributes.speed_note_count,
+                    ar: attributes.ar,
+                    od: attributes.od,
+                    hp: attributes.hp,
+                    n_circles: attributes.n_circles,
+                    n_sliders: attributes.n_sliders,
+                    n_slider_ticks: attributes.n_slider_ticks,
+                    n_spinners: attributes.n_spinners,
+                    stars: attributes.stars,
+                    max_combo: attributes.max_combo,
+                }
+            }
+        }
+
+        impl From<$crate::models::difficulty_attributes::StoredOsuAttributes> for $attributes {
+            fn from(
+                attributes: $crate::models::difficulty_attributes::StoredOsuAttributes,
+            ) -> Self {
+                Self {
+                    aim: attributes.aim,
+                    speed: attributes.speed,
+                    flashlight: attributes.flashlight,
+                    slider_factor: attributes.slider_factor,
+                    speed_note_count: attributes.speed_note_count,
+                    ar: attributes.ar,
+                    od: attributes.od,
+                    hp: attributes.hp,
+                    n_circles: attributes.n_circles,
+                    n_sliders: attributes.n_sliders,
+                    n_slider_ticks: attributes.n_slider_ticks,
+                    n_spinners: attributes.n_spinners,
+                    stars: attributes.stars,
+                    max_combo: attributes.max_combo,
+                }
+            }
+        }
+    )*};
+}
+
+pub(crate) use impl_stored_osu_attributes;
+
+impl_stored_osu_attributes!(OsuDifficultyAttributes);
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct StoredTaikoAttributes {
+    pub stamina: f64,
+    pub rhythm: f64,
+    pub color: f64,
+    pub peak: f64,
+    pub great_hit_window: f64,
+    pub ok_hit_window: f64,
+    pub mono_stamina_factor: f64,
+    pub stars: f64,
+    pub max_combo: u32,
+    pub is_convert: bool,
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct StoredCatchAttributes {
+    pub stars: f64,
+    pub ar: f64,
+    pub n_fruits: u32,
+    pub n_droplets: u32,
+    pub n_tiny_droplets: u32,
+    pub is_convert: bool,
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct StoredManiaAttributes {
+    pub stars: f64,
+    pub hit_window: f64,
+    pub n_objects: u32,
+    pub n_hold_notes: u32,
+    pub max_combo: u32,
+    pub is_convert: bool,
+}
+
+/// Difficulty attributes as they are kept in redis.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum StoredDifficultyAttributes {
+    Osu(StoredOsuAttributes),
+    Taiko(StoredTaikoAttributes),
+    Catch(StoredCatchAttributes),
+    Mania(StoredManiaAttributes),
+}
+
+impl From<&DifficultyAttributes> for StoredDifficultyAttributes {
+    fn from(attributes: &DifficultyAttributes) -> Self {
+        match attributes {
+            DifficultyAttributes::Osu(attributes) => Self::Osu(attributes.into()),
+            DifficultyAttributes::Taiko(attributes) => Self::Taiko(StoredTaikoAttributes {
+                stamina: attributes.stamina,
+                rhythm: attributes.rhythm,
+                color: attributes.color,
+                peak: attributes.peak,
+                great_hit_window: attributes.great_hit_window,
+                ok_hit_window: attributes.ok_hit_window,
+                mono_stamina_factor: attributes.mono_stamina_factor,
+                stars: attributes.stars,
+                max_combo: attributes.max_combo,
+                is_convert: attributes.is_convert,
+            }),
+            DifficultyAttributes::Catch(attributes) => Self::Catch(StoredCatchAttributes {
+                stars: attributes.stars,
+                ar: attributes.ar,
+                n_fruits: attributes.n_fruits,
+                n_droplets: attributes.n_droplets,
+                n_tiny_droplets: attributes.n_tiny_droplets,
+                is_convert: attributes.is_convert,
+            }),
+            DifficultyAttributes::Mania(attributes) => Self::Mania(StoredManiaAttributes {
+                stars: attributes.stars,
+                hit_window: attributes.hit_window,
+                n_objects: attributes.n_objects,
+                n_hold_notes: attributes.n_hold_notes,
+                max_combo: attributes.max_combo,
+                is_convert: attributes.is_convert,
+            }),
+        }
+    }
+}
+
+impl From<StoredDifficultyAttributes> for DifficultyAttributes {
+    fn from(attributes: StoredDifficultyAttributes) -> Self {
+        match attributes {
+            StoredDifficultyAttributes::Osu(attributes) => Self::Osu(attributes.into()),
+            StoredDifficultyAttributes::Taiko(attributes) => {
+                Self::Taiko(TaikoDifficultyAttributes {
+                    stamina: attributes.stamina,
+                    rhythm: attributes.rhythm,
+                    color: attributes.color,
+                    peak: attributes.peak,
+                    great_hit_window: attributes.great_hit_window,
+                    ok_hit_window: attributes.ok_hit_window,
+                    mono_stamina_factor: attributes.mono_stamina_factor,
+                    stars: attributes.stars,
+                    max_combo: attributes.max_combo,
+                    is_convert: attributes.is_convert,
+                })
+            }
+            StoredDifficultyAttributes::Catch(attributes) => {
+                Self::Catch(CatchDifficultyAttributes {
+                    stars: attributes.stars,
+                    ar: attributes.ar,
+                    n_fruits: attributes.n_fruits,
+                    n_droplets: attributes.n_droplets,
+                    n_tiny_droplets: attributes.n_tiny_droplets,
+                    is_convert: attributes.is_convert,
+                })
+            }
+            StoredDifficultyAttributes::Mania(attributes) => {
+                Self::Mania(ManiaDifficultyAttributes {
+                    stars: attributes.stars,
+                    hit_window: attributes.hit_window,
+                    n_objects: attributes.n_objects,
+                    n_hold_notes: attributes.n_hold_notes,
+                    max_combo: attributes.max_combo,
+                    is_convert: attributes.is_convert,
+                })
+            }
+        }
+    }
+}
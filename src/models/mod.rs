@@ -1,6 +1,14 @@
 pub mod beatmap;
+pub mod beatmap_cache;
+pub mod beatmap_source;
+pub mod circuit_breaker;
+pub mod difficulty_attributes;
 pub mod leaderboard;
+pub mod mode;
+pub mod mods;
+pub mod privileges;
 pub mod queue;
+pub mod replay;
 pub mod rework;
 pub mod score;
 pub mod stats;
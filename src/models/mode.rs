@@ -0,0 +1,154 @@
+use std::fmt;
+
+use akatsuki_pp_rs::model::mode::GameMode;
+
+/// A game mode, as stored in `play_mode` and `reworks.mode`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Mode {
+    Std,
+    Taiko,
+    Catch,
+    Mania,
+}
+
+impl Mode {
+    pub fn as_i32(self) -> i32 {
+        match self {
+            Self::Std => 0,
+            Self::Taiko => 1,
+            Self::Catch => 2,
+            Self::Mania => 3,
+        }
+    }
+
+    pub fn game_mode(self) -> GameMode {
+        match self {
+            Self::Std => GameMode::Osu,
+            Self::Taiko => GameMode::Taiko,
+            Self::Catch => GameMode::Catch,
+            Self::Mania => GameMode::Mania,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Std => "std",
+            Self::Taiko => "taiko",
+            Self::Catch => "catch",
+            Self::Mania => "mania",
+        }
+    }
+
+    /// The mode part of the `ripple:{leaderboard}:{mode}` redis keys.
+    pub fn stats_prefix(self) -> &'static str {
+        match self {
+            Self::Std => "std",
+            Self::Taiko => "taiko",
+            Self::Catch => "ctb",
+            Self::Mania => "mania",
+        }
+    }
+}
+
+impl TryFrom<i32> for Mode {
+    type Error = InvalidMode;
+
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Std),
+            1 => Ok(Self::Taiko),
+            2 => Ok(Self::Catch),
+            3 => Ok(Self::Mania),
+            _ => Err(InvalidMode(value)),
+        }
+    }
+}
+
+impl From<GameMode> for Mode {
+    fn from(game_mode: GameMode) -> Self {
+        match game_mode {
+            GameMode::Osu => Self::Std,
+            GameMode::Taiko => Self::Taiko,
+            GameMode::Catch => Self::Catch,
+            GameMode::Mania => Self::Mania,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidMode(pub i32);
+
+impl fmt::Display for InvalidMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid mode {}, expected 0 (std), 1 (taiko), 2 (catch) or 3 (mania)",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for InvalidMode {}
+
+/// Which leaderboard a score belongs to, the `rx` of deploy scopes and reworks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RelaxKind {
+    Vanilla,
+    Relax,
+    Autopilot,
+}
+
+impl RelaxKind {
+    pub fn as_i32(self) -> i32 {
+        match self {
+            Self::Vanilla => 0,
+            Self::Relax => 1,
+            Self::Autopilot => 2,
+        }
+    }
+
+    pub fn scores_table(self) -> &'static str {
+        match self {
+            Self::Vanilla => "scores",
+            Self::Relax => "scores_relax",
+            Self::Autopilot => "scores_ap",
+        }
+    }
+
+    /// The leaderboard part of the `ripple:{leaderboard}:{mode}` redis keys.
+    pub fn redis_leaderboard(self) -> &'static str {
+        match self {
+            Self::Vanilla => "leaderboard",
+            Self::Relax => "relaxboard",
+            Self::Autopilot => "autoboard",
+        }
+    }
+}
+
+impl TryFrom<i32> for RelaxKind {
+    type Error = InvalidRelaxKind;
+
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Vanilla),
+            1 => Ok(Self::Relax),
+            2 => Ok(Self::Autopilot),
+            _ => Err(InvalidRelaxKind(value)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidRelaxKind(pub i32);
+
+impl fmt::Display for InvalidRelaxKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid rx {}, expected 0 (vanilla), 1 (relax) or 2 (autopilot)",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for InvalidRelaxKind {}
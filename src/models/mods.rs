@@ -0,0 +1,413 @@
+use std::fmt;
+
+use super::mode::Mode;
+
+pub const NO_FAIL: u32 = 1 << 0;
+pub const EASY: u32 = 1 << 1;
+pub const TOUCH_DEVICE: u32 = 1 << 2;
+pub const HIDDEN: u32 = 1 << 3;
+pub const HARD_ROCK: u32 = 1 << 4;
+pub const SUDDEN_DEATH: u32 = 1 << 5;
+pub const DOUBLE_TIME: u32 = 1 << 6;
+pub const RELAX: u32 = 1 << 7;
+pub const HALF_TIME: u32 = 1 << 8;
+pub const NIGHTCORE: u32 = 1 << 9;
+pub const FLASHLIGHT: u32 = 1 << 10;
+pub const AUTOPLAY: u32 = 1 << 11;
+pub const SPUN_OUT: u32 = 1 << 12;
+pub const AUTOPILOT: u32 = 1 << 13;
+pub const PERFECT: u32 = 1 << 14;
+pub const KEY_4: u32 = 1 << 15;
+pub const KEY_5: u32 = 1 << 16;
+pub const KEY_6: u32 = 1 << 17;
+pub const KEY_7: u32 = 1 << 18;
+pub const KEY_8: u32 = 1 << 19;
+pub const FADE_IN: u32 = 1 << 20;
+pub const RANDOM: u32 = 1 << 21;
+pub const CINEMA: u32 = 1 << 22;
+pub const TARGET: u32 = 1 << 23;
+pub const KEY_9: u32 = 1 << 24;
+pub const KEY_COOP: u32 = 1 << 25;
+pub const KEY_1: u32 = 1 << 26;
+pub const KEY_3: u32 = 1 << 27;
+pub const KEY_2: u32 = 1 << 28;
+pub const SCORE_V2: u32 = 1 << 29;
+pub const MIRROR: u32 = 1 << 30;
+
+/// Every stable mod with its acronym, in bit order.
+pub const ACRONYMS: [(u32, &str); 31] = [
+    (NO_FAIL, "NF"),
+    (EASY, "EZ"),
+    (TOUCH_DEVICE, "TD"),
+    (HIDDEN, "HD"),
+    (HARD_ROCK, "HR"),
+    (SUDDEN_DEATH, "SD"),
+    (DOUBLE_TIME, "DT"),
+    (RELAX, "RX"),
+    (HALF_TIME, "HT"),
+    (NIGHTCORE, "NC"),
+    (FLASHLIGHT, "FL"),
+    (AUTOPLAY, "AT"),
+    (SPUN_OUT, "SO"),
+    (AUTOPILOT, "AP"),
+    (PERFECT, "PF"),
+    (KEY_4, "4K"),
+    (KEY_5, "5K"),
+    (KEY_6, "6K"),
+    (KEY_7, "7K"),
+    (KEY_8, "8K"),
+    (FADE_IN, "FI"),
+    (RANDOM, "RD"),
+    (CINEMA, "CN"),
+    (TARGET, "TP"),
+    (KEY_9, "9K"),
+    (KEY_COOP, "CO"),
+    (KEY_1, "1K"),
+    (KEY_3, "3K"),
+    (KEY_2, "2K"),
+    (SCORE_V2, "V2"),
+    (MIRROR, "MR"),
+];
+
+const KNOWN_MODS: u32 = (1 << 31) - 1;
+const KEY_MODS: u32 =
+    KEY_1 | KEY_2 | KEY_3 | KEY_4 | KEY_5 | KEY_6 | KEY_7 | KEY_8 | KEY_9 | KEY_COOP;
+const MANIA_ONLY_MODS: u32 = KEY_MODS | FADE_IN | RANDOM | MIRROR;
+const STD_ONLY_MODS: u32 = AUTOPILOT | SPUN_OUT | TARGET;
+
+// no play exists with both, so there is no sensible way to pick one
+const INCOMPATIBLE_MODS: [(u32, u32); 6] = [
+    (EASY, HARD_ROCK),
+    (DOUBLE_TIME, HALF_TIME),
+    (NIGHTCORE, HALF_TIME),
+    (NO_FAIL, SUDDEN_DEATH),
+    (NO_FAIL, PERFECT),
+    (RELAX, AUTOPILOT),
+];
+
+pub fn acronym(bit: u32) -> &'static str {
+    ACRONYMS
+        .iter()
+        .find(|(mod_bit, _)| *mod_bit == bit)
+        .map_or("??", |(_, acronym)| acronym)
+}
+
+/// Parses a mod bitmask ("832") or acronyms ("HDDT", "+HR,NC", "hd dt"); "NM" and
+/// an empty string are no mods.
+pub fn parse(input: &str) -> Result<u32, InvalidMods> {
+    let input = input.trim();
+    if let Ok(mods) = input.parse::<u32>() {
+        return Ok(mods);
+    }
+
+    let acronyms: Vec<char> = input
+        .chars()
+        .filter(|c| !matches!(c, '+' | ',' | ' '))
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+
+    let mut mods = 0;
+    for chunk in acronyms.chunks(2) {
+        let acronym: String = chunk.iter().collect();
+        if acronym == "NM" {
+            continue;
+        }
+
+        let (bit, _) = ACRONYMS
+            .iter()
+            .find(|(_, known)| *known == acronym)
+            .ok_or(InvalidMods::UnknownAcronym(acronym))?;
+        mods |= bit;
+    }
+
+    Ok(mods)
+}
+
+/// Formats a bitmask as acronyms in bit order, e.g. "HDDT"; the inverse of `parse`.
+pub fn format(mods: u32) -> String {
+    if mods == 0 {
+        return "NM".to_string();
+    }
+
+    ACRONYMS
+        .iter()
+        .filter(|(bit, _)| mods & bit > 0)
+        .map(|(_, acronym)| *acronym)
+        .collect()
+}
+
+/// The clock rate the speed changing mods in `mods` imply.
+pub fn clock_rate(mods: u32) -> f64 {
+    if mods & (DOUBLE_TIME | NIGHTCORE) > 0 {
+        1.5
+    } else if mods & HALF_TIME > 0 {
+        0.75
+    } else {
+        1.0
+    }
+}
+
+/// Deserializes a mods field from either a bitmask or an acronym string.
+pub fn deserialize<'de, D>(deserializer: D) -> Result<i32, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    struct ModsVisitor;
+
+    impl<'de> serde::de::Visitor<'de> for ModsVisitor {
+        type Value = i32;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a mod bitmask or acronyms like \"HDDT\"")
+        }
+
+        fn visit_i64<E: serde::de::Error>(self, value: i64) -> Result<i32, E> {
+            i32::try_from(value).map_err(|_| E::custom(InvalidMods::UnknownBits(value as u32)))
+        }
+
+        fn visit_u64<E: serde::de::Error>(self, value: u64) -> Result<i32, E> {
+            i32::try_from(value).map_err(|_| E::custom(InvalidMods::UnknownBits(value as u32)))
+        }
+
+        fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<i32, E> {
+            parse(value).map(|mods| mods as i32).map_err(E::custom)
+        }
+    }
+
+    deserializer.deserialize_any(ModsVisitor)
+}
+
+fn mode_mods(mode: Mode) -> u32 {
+    match mode {
+        Mode::Std => KNOWN_MODS & !MANIA_ONLY_MODS,
+        Mode::Taiko | Mode::Catch => KNOWN_MODS & !MANIA_ONLY_MODS & !STD_ONLY_MODS,
+        Mode::Mania => KNOWN_MODS & !STD_ONLY_MODS & !RELAX,
+    }
+}
+
+#[derive(Debug)]
+pub enum InvalidMods {
+    UnknownBits(u32),
+    Incompatible(u32, u32),
+    Unplayable(u32),
+    NotInModes(u32),
+    UnknownAcronym(String),
+}
+
+impl fmt::Display for InvalidMods {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownBits(bits) => write!(f, "invalid mods: unknown mod bits {}", bits),
+            Self::Incompatible(a, b) => write!(
+                f,
+                "invalid mods: {} and {} cannot be combined",
+                acronym(*a),
+                acronym(*b)
+            ),
+            Self::Unplayable(bit) => write!(
+                f,
+                "invalid mods: {} plays have no performance",
+                acronym(*bit)
+            ),
+            Self::NotInModes(mods) => write!(
+                f,
+                "invalid mods: none of the mods in {} exist in the selected modes",
+                mods
+            ),
+            Self::UnknownAcronym(acronym) => {
+                write!(f, "invalid mods: unknown mod acronym {:?}", acronym)
+            }
+        }
+    }
+}
+
+impl std::error::Error for InvalidMods {}
+
+/// A mod mask as it is passed to the calculators, plus what was changed to get there.
+pub struct NormalisedMods {
+    pub mods: u32,
+    pub changes: Vec<String>,
+}
+
+/// Rejects masks no real play can have and rewrites the rest into the form the
+/// calculators expect: NC is folded into DT, PF into SD, and mods that do not
+/// exist in `mode` are dropped.
+pub fn normalise(mods: u32, mode: Mode) -> Result<NormalisedMods, InvalidMods> {
+    if mods & !KNOWN_MODS > 0 {
+        return Err(InvalidMods::UnknownBits(mods & !KNOWN_MODS));
+    }
+
+    for unplayable in [AUTOPLAY, CINEMA] {
+        if mods & unplayable > 0 {
+            return Err(InvalidMods::Unplayable(unplayable));
+        }
+    }
+
+    for (a, b) in INCOMPATIBLE_MODS {
+        if mods & a > 0 && mods & b > 0 {
+            return Err(InvalidMods::Incompatible(a, b));
+        }
+    }
+
+    let mut mods = mods;
+    let mut changes = Vec::new();
+
+    for (folded, into) in [(NIGHTCORE, DOUBLE_TIME), (PERFECT, SUDDEN_DEATH)] {
+        if mods & folded > 0 {
+            mods = (mods & !folded) | into;
+            changes.push(format!("folded {} into {}", acronym(folded), acronym(into)));
+        }
+    }
+
+    let invalid_for_mode = mods & !mode_mods(mode);
+    for (bit, acronym) in ACRONYMS {
+        if invalid_for_mode & bit > 0 {
+            changes.push(format!("removed {}, not a {} mod", acronym, mode.name()));
+        }
+    }
+    mods &= !invalid_for_mode;
+
+    Ok(NormalisedMods { mods, changes })
+}
+
+/// Checks a deploy mod filter, which matches scores having any of its mods.
+pub fn validate_filter(mods: u32, modes: &[Mode]) -> Result<(), InvalidMods> {
+    if mods & !KNOWN_MODS > 0 {
+        return Err(InvalidMods::UnknownBits(mods & !KNOWN_MODS));
+    }
+
+    if !modes.iter().any(|mode| mods & mode_mods(*mode) > 0) {
+        return Err(InvalidMods::NotInModes(mods));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_accepts_bitmasks_and_acronyms() {
+        assert_eq!(parse("72").unwrap(), HIDDEN | DOUBLE_TIME);
+        assert_eq!(parse("HDDT").unwrap(), HIDDEN | DOUBLE_TIME);
+        assert_eq!(parse(" hd dt ").unwrap(), HIDDEN | DOUBLE_TIME);
+        assert_eq!(parse("+HR,NC").unwrap(), HARD_ROCK | NIGHTCORE);
+        assert_eq!(parse("4KV2").unwrap(), KEY_4 | SCORE_V2);
+    }
+
+    #[test]
+    fn parse_treats_nm_and_empty_as_no_mods() {
+        assert_eq!(parse("").unwrap(), 0);
+        assert_eq!(parse("NM").unwrap(), 0);
+        assert_eq!(parse("nmHD").unwrap(), HIDDEN);
+    }
+
+    #[test]
+    fn parse_rejects_unknown_acronyms() {
+        assert!(matches!(
+            parse("HDXX"),
+            Err(InvalidMods::UnknownAcronym(acronym)) if acronym == "XX"
+        ));
+        assert!(matches!(
+            parse("HDD"),
+            Err(InvalidMods::UnknownAcronym(acronym)) if acronym == "D"
+        ));
+    }
+
+    #[test]
+    fn format_lists_acronyms_in_bit_order() {
+        assert_eq!(format(0), "NM");
+        assert_eq!(format(DOUBLE_TIME | HIDDEN), "HDDT");
+        assert_eq!(format(RELAX | HARD_ROCK | NO_FAIL), "NFHRRX");
+    }
+
+    #[test]
+    fn format_round_trips_through_parse() {
+        for mods in [
+            0,
+            HIDDEN,
+            HIDDEN | DOUBLE_TIME | FLASHLIGHT,
+            KEY_7 | MIRROR,
+            AUTOPILOT,
+        ] {
+            assert_eq!(parse(&format(mods)).unwrap(), mods);
+        }
+    }
+
+    #[test]
+    fn normalise_folds_nightcore_and_perfect() {
+        let normalised = normalise(HIDDEN | NIGHTCORE | PERFECT, Mode::Std).unwrap();
+
+        assert_eq!(normalised.mods, HIDDEN | DOUBLE_TIME | SUDDEN_DEATH);
+        assert_eq!(
+            normalised.changes,
+            vec!["folded NC into DT", "folded PF into SD"]
+        );
+    }
+
+    #[test]
+    fn normalise_drops_mods_of_other_modes() {
+        let normalised = normalise(HIDDEN | AUTOPILOT | KEY_4, Mode::Taiko).unwrap();
+
+        assert_eq!(normalised.mods, HIDDEN);
+        assert_eq!(
+            normalised.changes,
+            vec!["removed AP, not a taiko mod", "removed 4K, not a taiko mod"]
+        );
+
+        let normalised = normalise(RELAX | KEY_7, Mode::Mania).unwrap();
+        assert_eq!(normalised.mods, KEY_7);
+    }
+
+    #[test]
+    fn normalise_keeps_valid_mods_unchanged() {
+        let normalised = normalise(HIDDEN | HARD_ROCK | DOUBLE_TIME | RELAX, Mode::Std).unwrap();
+
+        assert_eq!(normalised.mods, HIDDEN | HARD_ROCK | DOUBLE_TIME | RELAX);
+        assert!(normalised.changes.is_empty());
+    }
+
+    #[test]
+    fn normalise_rejects_impossible_masks() {
+        assert!(matches!(
+            normalise(1 << 31, Mode::Std),
+            Err(InvalidMods::UnknownBits(bits)) if bits == 1 << 31
+        ));
+        assert!(matches!(
+            normalise(HIDDEN | AUTOPLAY, Mode::Std),
+            Err(InvalidMods::Unplayable(AUTOPLAY))
+        ));
+        assert!(matches!(
+            normalise(EASY | HARD_ROCK, Mode::Std),
+            Err(InvalidMods::Incompatible(EASY, HARD_ROCK))
+        ));
+        assert!(matches!(
+            normalise(RELAX | AUTOPILOT, Mode::Std),
+            Err(InvalidMods::Incompatible(RELAX, AUTOPILOT))
+        ));
+        assert!(matches!(
+            normalise(NIGHTCORE | HALF_TIME, Mode::Std),
+            Err(InvalidMods::Incompatible(NIGHTCORE, HALF_TIME))
+        ));
+    }
+
+    #[test]
+    fn validate_filter_needs_a_mod_of_the_selected_modes() {
+        assert!(validate_filter(HIDDEN, &[Mode::Std]).is_ok());
+        assert!(validate_filter(AUTOPILOT | KEY_4, &[Mode::Taiko, Mode::Mania]).is_ok());
+
+        assert!(matches!(
+            validate_filter(AUTOPILOT, &[Mode::Taiko, Mode::Catch]),
+            Err(InvalidMods::NotInModes(AUTOPILOT))
+        ));
+        assert!(matches!(
+            validate_filter(KEY_4, &[Mode::Std]),
+            Err(InvalidMods::NotInModes(KEY_4))
+        ));
+        assert!(matches!(
+            validate_filter(HIDDEN | 1 << 31, &[Mode::Std]),
+            Err(InvalidMods::UnknownBits(_))
+        ));
+    }
+}
@@ -0,0 +1,5 @@
+//! Bits of the `users.privileges` column.
+
+/// Unset for restricted users.
+pub const USER_PUBLIC: i32 = 1 << 0;
+pub const ADMIN_MANAGE_BEATMAPS: i32 = 1 << 8;
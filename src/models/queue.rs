@@ -1,5 +1,5 @@
 #[derive(
-    serde::Serialize, serde::Deserialize, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize,
+    Clone, serde::Serialize, serde::Deserialize, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize,
 )]
 #[archive(compare(PartialEq))]
 #[archive_attr(derive(bytecheck::CheckBytes))]
@@ -0,0 +1,232 @@
+use std::fmt;
+
+/// The score fields at the start of an .osr file, before the compressed replay frames.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct ReplayHeader {
+    pub mode: i32,
+    pub version: i32,
+    pub beatmap_md5: String,
+    pub player_name: String,
+    pub replay_md5: String,
+    pub count_300: i32,
+    pub count_100: i32,
+    pub count_50: i32,
+    pub count_geki: i32,
+    pub count_katu: i32,
+    pub miss_count: i32,
+    pub score: i32,
+    pub max_combo: i32,
+    pub perfect: bool,
+    pub mods: i32,
+}
+
+#[derive(Debug)]
+pub enum InvalidReplay {
+    UnexpectedEof,
+    InvalidString,
+    MissingBeatmapMd5,
+}
+
+impl fmt::Display for InvalidReplay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnexpectedEof => write!(f, "invalid replay: file ends inside the header"),
+            Self::InvalidString => write!(f, "invalid replay: malformed string in the header"),
+            Self::MissingBeatmapMd5 => write!(f, "invalid replay: no beatmap md5"),
+        }
+    }
+}
+
+impl std::error::Error for InvalidReplay {}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], InvalidReplay> {
+        if self.bytes.len() < len {
+            return Err(InvalidReplay::UnexpectedEof);
+        }
+
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, InvalidReplay> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, InvalidReplay> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn i32(&mut self) -> Result<i32, InvalidReplay> {
+        let bytes = self.take(4)?;
+        Ok(i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn uleb128(&mut self) -> Result<usize, InvalidReplay> {
+        let mut value = 0usize;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            value |= ((byte & 0x7f) as usize) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        Err(InvalidReplay::InvalidString)
+    }
+
+    /// A 0x00 byte for an empty string, or 0x0b followed by the ULEB128 length & UTF-8.
+    fn string(&mut self) -> Result<String, InvalidReplay> {
+        match self.u8()? {
+            0x00 => Ok(String::new()),
+            0x0b => {
+                let len = self.uleb128()?;
+                let bytes = self.take(len)?;
+                String::from_utf8(bytes.to_vec()).map_err(|_| InvalidReplay::InvalidString)
+            }
+            _ => Err(InvalidReplay::InvalidString),
+        }
+    }
+}
+
+impl ReplayHeader {
+    pub fn parse(bytes: &[u8]) -> Result<Self, InvalidReplay> {
+        let mut reader = Reader { bytes };
+
+        let header = Self {
+            mode: reader.u8()? as i32,
+            version: reader.i32()?,
+            beatmap_md5: reader.string()?,
+            player_name: reader.string()?,
+            replay_md5: reader.string()?,
+            count_300: reader.u16()? as i32,
+            count_100: reader.u16()? as i32,
+            count_50: reader.u16()? as i32,
+            count_geki: reader.u16()? as i32,
+            count_katu: reader.u16()? as i32,
+            miss_count: reader.u16()? as i32,
+            score: reader.i32()?,
+            max_combo: reader.u16()? as i32,
+            perfect: reader.u8()? != 0,
+            mods: reader.i32()?,
+        };
+
+        if header.beatmap_md5.is_empty() {
+            return Err(InvalidReplay::MissingBeatmapMd5);
+        }
+
+        Ok(header)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(value: &str) -> Vec<u8> {
+        if value.is_empty() {
+            return vec![0x00];
+        }
+
+        let mut bytes = vec![0x0b];
+        let mut len = value.len();
+        loop {
+            let byte = (len & 0x7f) as u8;
+            len >>= 7;
+            if len == 0 {
+                bytes.push(byte);
+                break;
+            }
+            bytes.push(byte | 0x80);
+        }
+        bytes.extend_from_slice(value.as_bytes());
+        bytes
+    }
+
+    fn replay(beatmap_md5: &str, player_name: &str) -> Vec<u8> {
+        let mut bytes = vec![0];
+        bytes.extend_from_slice(&20210520i32.to_le_bytes());
+        bytes.extend(string(beatmap_md5));
+        bytes.extend(string(player_name));
+        bytes.extend(string("0f0e0d0c0b0a09080706050403020100"));
+        for count in [812u16, 21, 3, 150, 12, 2] {
+            bytes.extend_from_slice(&count.to_le_bytes());
+        }
+        bytes.extend_from_slice(&12_345_678i32.to_le_bytes());
+        bytes.extend_from_slice(&1024u16.to_le_bytes());
+        bytes.push(0);
+        bytes.extend_from_slice(&(8i32 | 64).to_le_bytes());
+        // the compressed frames that follow are not read
+        bytes.extend_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+        bytes
+    }
+
+    #[test]
+    fn parse_reads_the_score_fields() {
+        let header =
+            ReplayHeader::parse(&replay("a5b99395a42bd55bc5eb1d2411cbdf8b", "player")).unwrap();
+
+        assert_eq!(header.mode, 0);
+        assert_eq!(header.version, 20210520);
+        assert_eq!(header.beatmap_md5, "a5b99395a42bd55bc5eb1d2411cbdf8b");
+        assert_eq!(header.player_name, "player");
+        assert_eq!(header.replay_md5, "0f0e0d0c0b0a09080706050403020100");
+        assert_eq!(header.count_300, 812);
+        assert_eq!(header.count_100, 21);
+        assert_eq!(header.count_50, 3);
+        assert_eq!(header.count_geki, 150);
+        assert_eq!(header.count_katu, 12);
+        assert_eq!(header.miss_count, 2);
+        assert_eq!(header.score, 12_345_678);
+        assert_eq!(header.max_combo, 1024);
+        assert!(!header.perfect);
+        assert_eq!(header.mods, 72);
+    }
+
+    #[test]
+    fn parse_reads_multi_byte_string_lengths() {
+        let player_name = "a".repeat(200);
+        let header =
+            ReplayHeader::parse(&replay("a5b99395a42bd55bc5eb1d2411cbdf8b", &player_name)).unwrap();
+
+        assert_eq!(header.player_name, player_name);
+    }
+
+    #[test]
+    fn parse_rejects_truncated_headers() {
+        let bytes = replay("a5b99395a42bd55bc5eb1d2411cbdf8b", "player");
+
+        for len in [0, 1, 5, 20, bytes.len() - 8] {
+            assert!(matches!(
+                ReplayHeader::parse(&bytes[..len]),
+                Err(InvalidReplay::UnexpectedEof)
+            ));
+        }
+    }
+
+    #[test]
+    fn parse_rejects_malformed_strings() {
+        let mut bytes = replay("a5b99395a42bd55bc5eb1d2411cbdf8b", "player");
+        // the beatmap md5 string marker
+        bytes[5] = 0x0c;
+
+        assert!(matches!(
+            ReplayHeader::parse(&bytes),
+            Err(InvalidReplay::InvalidString)
+        ));
+    }
+
+    #[test]
+    fn parse_rejects_replays_without_a_beatmap_md5() {
+        assert!(matches!(
+            ReplayHeader::parse(&replay("", "player")),
+            Err(InvalidReplay::MissingBeatmapMd5)
+        ));
+    }
+}
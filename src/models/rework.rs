@@ -1,3 +1,5 @@
+use super::mode::{InvalidMode, InvalidRelaxKind, Mode, RelaxKind};
+
 #[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow)]
 pub struct Rework {
     pub rework_id: i32,
@@ -7,3 +9,13 @@ pub struct Rework {
     #[serde(with = "chrono::serde::ts_seconds")]
     pub updated_at: chrono::DateTime<chrono::Utc>,
 }
+
+impl Rework {
+    pub fn mode(&self) -> Result<Mode, InvalidMode> {
+        Mode::try_from(self.mode)
+    }
+
+    pub fn relax_kind(&self) -> Result<RelaxKind, InvalidRelaxKind> {
+        RelaxKind::try_from(self.rx)
+    }
+}
@@ -193,3 +193,26 @@ pub struct RippleScore {
     pub beatmapset_id: i32,
     pub song_name: String,
 }
+
+/// Some old scores only stored accuracy, with every hit result left at 0.
+pub fn has_hit_results(
+    count_300: i32,
+    count_100: i32,
+    count_50: i32,
+    count_gekis: i32,
+    count_katus: i32,
+) -> bool {
+    count_300 + count_100 + count_50 + count_gekis + count_katus > 0
+}
+
+impl RippleScore {
+    pub fn has_hit_results(&self) -> bool {
+        has_hit_results(
+            self.count_300,
+            self.count_100,
+            self.count_50,
+            self.count_gekis,
+            self.count_katus,
+        )
+    }
+}
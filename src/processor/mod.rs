@@ -1,7 +1,7 @@
 use std::{ops::DerefMut, sync::Arc, time::Duration};
 
 use lapin::{
-    options::{BasicAckOptions, BasicConsumeOptions, QueueDeclareOptions},
+    options::{BasicAckOptions, BasicConsumeOptions, BasicNackOptions, QueueDeclareOptions},
     types::FieldTable,
 };
 use redis::AsyncCommands;
@@ -11,6 +11,10 @@ use tokio_stream::StreamExt;
 use crate::{
     context::Context,
     models::{
+        difficulty_attributes::{
+            impl_stored_osu_attributes, DifficultyAttributesKey, StoredDifficultyAttributes,
+            StoredOsuAttributes,
+        },
         queue::QueueRequest,
         rework::Rework,
         score::{ReworkScore, RippleScore},
@@ -27,18 +31,39 @@ use remove_accuracy_pp::Beatmap as RemoveAccuracyBeatmap;
 use remove_manual_adjustments::Beatmap as RemoveManualAdjustmentsBeatmap;
 use stream_nerf_speed_value::Beatmap as StreamNerfSpeedValueBeatmap;
 
+/// The parts of a score the rework calculators look at.
+pub struct ReworkPlay {
+    pub mods: i32,
+    pub max_combo: i32,
+    pub count_300: i32,
+    pub count_100: i32,
+    pub count_50: i32,
+    pub count_misses: i32,
+}
+
+impl From<&RippleScore> for ReworkPlay {
+    fn from(score: &RippleScore) -> Self {
+        Self {
+            mods: score.mods,
+            max_combo: score.max_combo,
+            count_300: score.count_300,
+            count_100: score.count_100,
+            count_50: score.count_50,
+            count_misses: score.count_misses,
+        }
+    }
+}
+
 fn round(x: f32, decimals: u32) -> f32 {
     let y = 10i32.pow(decimals) as f32;
     (x * y).round() / y
 }
 
 async fn calculate_improved_miss_penalty_pp(
-    score: &RippleScore,
-    context: Arc<Context>,
+    score: &ReworkPlay,
+    beatmap_bytes: &[u8],
 ) -> anyhow::Result<f32> {
-    let beatmap_bytes =
-        usecases::beatmaps::fetch_beatmap_osu_file(score.beatmap_id, context).await?;
-    let beatmap = ImprovedMissPenaltyBeatmap::from_bytes(&beatmap_bytes).await?;
+    let beatmap = ImprovedMissPenaltyBeatmap::from_bytes(beatmap_bytes).await?;
 
     let result = improved_miss_penalty::osu_2019::OsuPP::new(&beatmap)
         .mods(score.mods as u32)
@@ -58,12 +83,10 @@ async fn calculate_improved_miss_penalty_pp(
 }
 
 async fn calculate_flashlight_hotfix_pp(
-    score: &RippleScore,
-    context: Arc<Context>,
+    score: &ReworkPlay,
+    beatmap_bytes: &[u8],
 ) -> anyhow::Result<f32> {
-    let beatmap_bytes =
-        usecases::beatmaps::fetch_beatmap_osu_file(score.beatmap_id, context).await?;
-    let beatmap = FlashlightHotfixBeatmap::from_bytes(&beatmap_bytes).await?;
+    let beatmap = FlashlightHotfixBeatmap::from_bytes(beatmap_bytes).await?;
 
     let result = flashlight_hotfix::osu_2019::OsuPP::new(&beatmap)
         .mods(score.mods as u32)
@@ -83,12 +106,10 @@ async fn calculate_flashlight_hotfix_pp(
 }
 
 async fn calculate_remove_accuracy_pp(
-    score: &RippleScore,
-    context: Arc<Context>,
+    score: &ReworkPlay,
+    beatmap_bytes: &[u8],
 ) -> anyhow::Result<f32> {
-    let beatmap_bytes =
-        usecases::beatmaps::fetch_beatmap_osu_file(score.beatmap_id, context).await?;
-    let beatmap = RemoveAccuracyBeatmap::from_bytes(&beatmap_bytes).await?;
+    let beatmap = RemoveAccuracyBeatmap::from_bytes(beatmap_bytes).await?;
 
     let result = remove_accuracy_pp::osu_2019::OsuPP::new(&beatmap)
         .mods(score.mods as u32)
@@ -108,12 +129,10 @@ async fn calculate_remove_accuracy_pp(
 }
 
 async fn calculate_stream_nerf_speed_value_pp(
-    score: &RippleScore,
-    context: Arc<Context>,
+    score: &ReworkPlay,
+    beatmap_bytes: &[u8],
 ) -> anyhow::Result<f32> {
-    let beatmap_bytes =
-        usecases::beatmaps::fetch_beatmap_osu_file(score.beatmap_id, context).await?;
-    let beatmap = StreamNerfSpeedValueBeatmap::from_bytes(&beatmap_bytes).await?;
+    let beatmap = StreamNerfSpeedValueBeatmap::from_bytes(beatmap_bytes).await?;
 
     let result = stream_nerf_speed_value::osu_2019::OsuPP::new(&beatmap)
         .mods(score.mods as u32)
@@ -133,12 +152,10 @@ async fn calculate_stream_nerf_speed_value_pp(
 }
 
 async fn calculate_remove_manual_adjustments_pp(
-    score: &RippleScore,
-    context: Arc<Context>,
+    score: &ReworkPlay,
+    beatmap_bytes: &[u8],
 ) -> anyhow::Result<f32> {
-    let beatmap_bytes =
-        usecases::beatmaps::fetch_beatmap_osu_file(score.beatmap_id, context).await?;
-    let beatmap = RemoveManualAdjustmentsBeatmap::from_bytes(&beatmap_bytes).await?;
+    let beatmap = RemoveManualAdjustmentsBeatmap::from_bytes(beatmap_bytes).await?;
 
     let result = remove_manual_adjustments::osu_2019::OsuPP::new(&beatmap)
         .mods(score.mods as u32)
@@ -158,12 +175,10 @@ async fn calculate_remove_manual_adjustments_pp(
 }
 
 async fn calculate_fix_inconsistent_powers_pp(
-    score: &RippleScore,
-    context: Arc<Context>,
+    score: &ReworkPlay,
+    beatmap_bytes: &[u8],
 ) -> anyhow::Result<f32> {
-    let beatmap_bytes =
-        usecases::beatmaps::fetch_beatmap_osu_file(score.beatmap_id, context).await?;
-    let beatmap = FixInconsistentPowersBeatmap::from_bytes(&beatmap_bytes).await?;
+    let beatmap = FixInconsistentPowersBeatmap::from_bytes(beatmap_bytes).await?;
 
     let result = fix_inconsistent_powers::osu_2019::OsuPP::new(&beatmap)
         .mods(score.mods as u32)
@@ -183,12 +198,10 @@ async fn calculate_fix_inconsistent_powers_pp(
 }
 
 async fn calculate_aim_accuracy_fix_pp(
-    score: &RippleScore,
-    context: Arc<Context>,
+    score: &ReworkPlay,
+    beatmap_bytes: &[u8],
 ) -> anyhow::Result<f32> {
-    let beatmap_bytes =
-        usecases::beatmaps::fetch_beatmap_osu_file(score.beatmap_id, context).await?;
-    let beatmap = AimAccuracyFixBeatmap::from_bytes(&beatmap_bytes)?;
+    let beatmap = AimAccuracyFixBeatmap::from_bytes(beatmap_bytes)?;
 
     let result = aim_accuracy_fix::osu_2019::OsuPP::from_map(&beatmap)
         .mods(score.mods as u32)
@@ -208,12 +221,10 @@ async fn calculate_aim_accuracy_fix_pp(
 }
 
 async fn calculate_improved_miss_penalty_and_acc_rework_pp(
-    score: &RippleScore,
-    context: Arc<Context>,
+    score: &ReworkPlay,
+    beatmap_bytes: &[u8],
 ) -> anyhow::Result<f32> {
-    let beatmap_bytes =
-        usecases::beatmaps::fetch_beatmap_osu_file(score.beatmap_id, context).await?;
-    let beatmap = improved_miss_penalty_and_acc_rework::Beatmap::from_bytes(&beatmap_bytes)?;
+    let beatmap = improved_miss_penalty_and_acc_rework::Beatmap::from_bytes(beatmap_bytes)?;
 
     let result = improved_miss_penalty_and_acc_rework::osu_2019::OsuPP::from_map(&beatmap)
         .mods(score.mods as u32)
@@ -233,12 +244,10 @@ async fn calculate_improved_miss_penalty_and_acc_rework_pp(
 }
 
 async fn calculate_everything_at_once_pp(
-    score: &RippleScore,
-    context: Arc<Context>,
+    score: &ReworkPlay,
+    beatmap_bytes: &[u8],
 ) -> anyhow::Result<f32> {
-    let beatmap_bytes =
-        usecases::beatmaps::fetch_beatmap_osu_file(score.beatmap_id, context).await?;
-    let beatmap = everything_at_once::Beatmap::from_bytes(&beatmap_bytes)?;
+    let beatmap = everything_at_once::Beatmap::from_bytes(beatmap_bytes)?;
 
     let result = everything_at_once::osu_2019::OsuPP::from_map(&beatmap)
         .mods(score.mods as u32)
@@ -258,12 +267,10 @@ async fn calculate_everything_at_once_pp(
 }
 
 async fn calculate_kippy_attempt_pp(
-    score: &RippleScore,
-    context: Arc<Context>,
+    score: &ReworkPlay,
+    beatmap_bytes: &[u8],
 ) -> anyhow::Result<f32> {
-    let beatmap_bytes =
-        usecases::beatmaps::fetch_beatmap_osu_file(score.beatmap_id, context).await?;
-    let beatmap = kippy_attempt::Beatmap::from_bytes(&beatmap_bytes)?;
+    let beatmap = kippy_attempt::Beatmap::from_bytes(beatmap_bytes)?;
 
     let result = kippy_attempt::osu_2019::OsuPP::from_map(&beatmap)
         .mods(score.mods as u32)
@@ -282,6 +289,183 @@ async fn calculate_kippy_attempt_pp(
     Ok(pp)
 }
 
+impl_stored_osu_attributes!(
+    aim_accuracy_fix::osu::OsuDifficultyAttributes,
+    improved_miss_penalty_and_acc_rework::osu::OsuDifficultyAttributes,
+    everything_at_once::osu::OsuDifficultyAttributes,
+);
+
+/// Reworks on an akatsuki-pp revision whose 2019 calculator can start from stored
+/// difficulty attributes, with that revision. The older ones always work them out.
+const STORED_ATTRIBUTE_REWORKS: [(i32, &str); 3] =
+    [(26, "2bb69c8"), (27, "c5cb35b"), (28, "6d22dcd")];
+
+fn rework_difficulty_attributes(
+    rework_id: i32,
+    beatmap_bytes: &[u8],
+    mods: i32,
+) -> anyhow::Result<StoredOsuAttributes> {
+    let mods = mods as u32;
+
+    let attributes = match rework_id {
+        26 => {
+            let beatmap = AimAccuracyFixBeatmap::from_bytes(beatmap_bytes)?;
+            let attributes = aim_accuracy_fix::osu_2019::stars::stars(&beatmap, mods.into(), None);
+            StoredOsuAttributes::from(&attributes)
+        }
+        27 => {
+            let beatmap = improved_miss_penalty_and_acc_rework::Beatmap::from_bytes(beatmap_bytes)?;
+            let attributes = improved_miss_penalty_and_acc_rework::osu_2019::stars::stars(
+                &beatmap,
+                mods.into(),
+                None,
+            );
+            StoredOsuAttributes::from(&attributes)
+        }
+        28 => {
+            let beatmap = everything_at_once::Beatmap::from_bytes(beatmap_bytes)?;
+            let attributes =
+                everything_at_once::osu_2019::stars::stars(&beatmap, mods.into(), None);
+            StoredOsuAttributes::from(&attributes)
+        }
+        _ => anyhow::bail!("rework {} cannot use stored attributes", rework_id),
+    };
+
+    Ok(attributes)
+}
+
+fn calculate_rework_pp_from_attributes(
+    rework_id: i32,
+    score: &ReworkPlay,
+    attributes: StoredOsuAttributes,
+) -> anyhow::Result<f32> {
+    let pp = match rework_id {
+        26 => {
+            aim_accuracy_fix::osu_2019::OsuPP::from_attributes(attributes.into())
+                .mods(score.mods as u32)
+                .combo(score.max_combo as u32)
+                .n300(score.count_300 as u32)
+                .n100(score.count_100 as u32)
+                .n50(score.count_50 as u32)
+                .misses(score.count_misses as u32)
+                .calculate()
+                .pp
+        }
+        27 => {
+            improved_miss_penalty_and_acc_rework::osu_2019::OsuPP::from_attributes(
+                attributes.into(),
+            )
+            .mods(score.mods as u32)
+            .combo(score.max_combo as u32)
+            .n300(score.count_300 as u32)
+            .n100(score.count_100 as u32)
+            .n50(score.count_50 as u32)
+            .misses(score.count_misses as u32)
+            .calculate()
+            .pp
+        }
+        28 => {
+            everything_at_once::osu_2019::OsuPP::from_attributes(attributes.into())
+                .mods(score.mods as u32)
+                .combo(score.max_combo as u32)
+                .n300(score.count_300 as u32)
+                .n100(score.count_100 as u32)
+                .n50(score.count_50 as u32)
+                .misses(score.count_misses as u32)
+                .calculate()
+                .pp
+        }
+        _ => anyhow::bail!("rework {} cannot use stored attributes", rework_id),
+    };
+
+    let mut pp = round(pp as f32, 2);
+    if pp.is_infinite() || pp.is_nan() {
+        pp = 0.0;
+    }
+
+    Ok(pp)
+}
+
+/// Calculates a score with attributes stored by an earlier run when there are some,
+/// so the beatmap is only fetched and parsed on a miss.
+async fn calculate_rework_pp_with_stored_attributes(
+    rework_id: i32,
+    version: &'static str,
+    score: &RippleScore,
+    context: Arc<Context>,
+) -> anyhow::Result<f32> {
+    let stored_key = DifficultyAttributesKey {
+        calculator: format!("rework-{}", rework_id),
+        version,
+        beatmap_md5: score.beatmap_md5.clone(),
+        mode: score.play_mode,
+        mods: score.mods as u32,
+    };
+
+    let stored = usecases::difficulty_attributes::fetch(&stored_key, context.clone()).await;
+    let attributes = match stored {
+        Some(StoredDifficultyAttributes::Osu(attributes)) => attributes,
+        _ => {
+            // only the version the score was set on gives its pp, and the key says so
+            let beatmap = usecases::beatmaps::fetch_verified_beatmap(
+                score.beatmap_id,
+                &score.beatmap_md5,
+                context.clone(),
+            )
+            .await?;
+            let attributes = rework_difficulty_attributes(rework_id, &beatmap.bytes, score.mods)?;
+
+            usecases::difficulty_attributes::store(
+                &stored_key,
+                &StoredDifficultyAttributes::Osu(attributes.clone()),
+                context,
+            )
+            .await;
+
+            attributes
+        }
+    };
+
+    calculate_rework_pp_from_attributes(rework_id, &ReworkPlay::from(score), attributes)
+}
+
+/// The rework calculators that can be run, by rework id and crate name.
+pub const REWORK_CALCULATORS: [(i32, &str); 10] = [
+    (19, "improved-miss-penalty"),
+    (21, "flashlight-hotfix"),
+    (22, "remove-accuracy-pp"),
+    (23, "stream-nerf-speed-value"),
+    (24, "remove-manual-adjustments"),
+    (25, "fix-inconsistent-powers"),
+    (26, "aim-accuracy-fix"),
+    (27, "improved-miss-penalty-and-acc-rework"),
+    (28, "everything-at-once"),
+    (29, "kippy-attempt"),
+];
+
+/// Calculates a play with the calculator of a rework, without touching the database.
+pub async fn calculate_rework_pp(
+    rework_id: i32,
+    score: &ReworkPlay,
+    beatmap_bytes: &[u8],
+) -> anyhow::Result<f32> {
+    let pp = match rework_id {
+        19 => calculate_improved_miss_penalty_pp(score, beatmap_bytes).await?,
+        21 => calculate_flashlight_hotfix_pp(score, beatmap_bytes).await?,
+        22 => calculate_remove_accuracy_pp(score, beatmap_bytes).await?,
+        23 => calculate_stream_nerf_speed_value_pp(score, beatmap_bytes).await?,
+        24 => calculate_remove_manual_adjustments_pp(score, beatmap_bytes).await?,
+        25 => calculate_fix_inconsistent_powers_pp(score, beatmap_bytes).await?,
+        26 => calculate_aim_accuracy_fix_pp(score, beatmap_bytes).await?,
+        27 => calculate_improved_miss_penalty_and_acc_rework_pp(score, beatmap_bytes).await?,
+        28 => calculate_everything_at_once_pp(score, beatmap_bytes).await?,
+        29 => calculate_kippy_attempt_pp(score, beatmap_bytes).await?,
+        _ => anyhow::bail!("no calculator for rework {}", rework_id),
+    };
+
+    Ok(pp)
+}
+
 async fn process_scores(
     rework: &Rework,
     scores: Vec<RippleScore>,
@@ -289,19 +473,28 @@ async fn process_scores(
 ) -> anyhow::Result<Vec<ReworkScore>> {
     let mut rework_scores: Vec<ReworkScore> = Vec::new();
 
+    let stored_attributes_version = STORED_ATTRIBUTE_REWORKS
+        .iter()
+        .find(|(rework_id, _)| *rework_id == rework.rework_id)
+        .map(|(_, version)| *version);
+
     for score in &scores {
-        let new_pp = match rework.rework_id {
-            19 => calculate_improved_miss_penalty_pp(score, context.clone()).await?,
-            21 => calculate_flashlight_hotfix_pp(score, context.clone()).await?,
-            22 => calculate_remove_accuracy_pp(score, context.clone()).await?,
-            23 => calculate_stream_nerf_speed_value_pp(score, context.clone()).await?,
-            24 => calculate_remove_manual_adjustments_pp(score, context.clone()).await?,
-            25 => calculate_fix_inconsistent_powers_pp(score, context.clone()).await?,
-            26 => calculate_aim_accuracy_fix_pp(score, context.clone()).await?,
-            27 => calculate_improved_miss_penalty_and_acc_rework_pp(score, context.clone()).await?,
-            28 => calculate_everything_at_once_pp(score, context.clone()).await?,
-            29 => calculate_kippy_attempt_pp(score, context.clone()).await?,
-            _ => unreachable!(),
+        let new_pp = match stored_attributes_version {
+            Some(version) => {
+                calculate_rework_pp_with_stored_attributes(
+                    rework.rework_id,
+                    version,
+                    score,
+                    context.clone(),
+                )
+                .await?
+            }
+            None => {
+                let beatmap =
+                    usecases::beatmaps::fetch_beatmap(score.beatmap_id, context.clone()).await?;
+                calculate_rework_pp(rework.rework_id, &ReworkPlay::from(score), &beatmap.bytes)
+                    .await?
+            }
         };
 
         log::info!(
@@ -329,19 +522,38 @@ fn calculate_new_pp(scores: &Vec<ReworkScore>, score_count: i32) -> i32 {
     total_pp.round() as i32
 }
 
+/// Also checks the rework's mode, so a bad row is refused before any score is read.
+fn rework_scores_table(rework: &Rework) -> anyhow::Result<&'static str> {
+    rework.mode()?;
+    let relax_kind = rework.relax_kind()?;
+
+    Ok(relax_kind.scores_table())
+}
+
 async fn handle_queue_request(
     request: QueueRequest,
     context: Arc<Context>,
     delivery_tag: u64,
 ) -> anyhow::Result<()> {
-    let Some(rework) = usecases::reworks::fetch_one(request.rework_id, context.clone()).await? else {
+    let Some(rework) = usecases::reworks::fetch_one(request.rework_id, context.clone()).await?
+    else {
         anyhow::bail!("failed to find rework");
     };
-    let scores_table = match rework.rx {
-        0 => "scores",
-        1 => "scores_relax",
-        2 => "scores_ap",
-        _ => unreachable!(),
+    let scores_table = match rework_scores_table(&rework) {
+        Ok(scores_table) => scores_table,
+        Err(e) => {
+            log::error!(
+                rework_id = rework.rework_id,
+                error = e.to_string();
+                "Skipping queue request for rework with invalid mode or rx",
+            );
+
+            context
+                .amqp_channel
+                .basic_ack(delivery_tag, BasicAckOptions::default())
+                .await?;
+            return Ok(());
+        }
     };
 
     let scores: Vec<RippleScore> = sqlx::query_as(
@@ -495,15 +707,41 @@ async fn rmq_listen(context: Arc<Context>) -> anyhow::Result<()> {
                 deserialized_data.rework_id
             );
 
-            let result = handle_queue_request(
-                deserialized_data,
-                context.clone(),
-                delivery.delivery_tag.clone(),
-            )
+            // retry the request once the beatmap source recovers, rather than
+            // dropping every request that arrives during an outage
+            let result = usecases::beatmaps::retry_while_source_unavailable(&context, || {
+                handle_queue_request(
+                    deserialized_data.clone(),
+                    context.clone(),
+                    delivery.delivery_tag.clone(),
+                )
+            })
             .await;
 
-            if let Err(e) = result {
-                log::error!(error = e.to_string(); "Error processing queue request");
+            match result {
+                Err(e) if usecases::beatmaps::is_source_unavailable(&e) => {
+                    log::warn!(
+                        user_id = deserialized_data.user_id,
+                        rework_id = deserialized_data.rework_id,
+                        error = e.to_string();
+                        "Beatmap source stayed unavailable, requeueing queue request",
+                    );
+
+                    context
+                        .amqp_channel
+                        .basic_nack(
+                            delivery.delivery_tag,
+                            BasicNackOptions {
+                                requeue: true,
+                                ..Default::default()
+                            },
+                        )
+                        .await?;
+                }
+                Err(e) => {
+                    log::error!(error = e.to_string(); "Error processing queue request");
+                }
+                Ok(()) => {}
             }
         }
 
@@ -522,3 +760,120 @@ pub async fn serve(context: Context) -> anyhow::Result<()> {
         rmq_listen(context_arc.clone()).await?;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A short std map of jumps with a few sliders, enough for every calculator to
+    /// produce non-zero attributes.
+    fn test_beatmap() -> Vec<u8> {
+        let mut osu = String::from(
+            "osu file format v14\n\n\
+             [General]\nMode: 0\n\n\
+             [Difficulty]\nHPDrainRate:5\nCircleSize:4\nOverallDifficulty:8\n\
+             ApproachRate:9\nSliderMultiplier:1.4\nSliderTickRate:1\n\n\
+             [TimingPoints]\n1000,300,4,2,0,60,1,0\n\n\
+             [HitObjects]\n",
+        );
+
+        for idx in 0..60 {
+            let time = 1000 + idx * 150;
+            let (x, y) = if idx % 2 == 0 { (64, 96) } else { (448, 288) };
+            if idx % 10 == 9 {
+                osu += &format!(
+                    "{x},{y},{time},2,0,L|{}:{y},1,140\n",
+                    x + 140 - 280 * (idx % 2)
+                );
+            } else {
+                osu += &format!("{x},{y},{time},1,0,0:0:0:0:\n");
+            }
+        }
+
+        osu.into_bytes()
+    }
+
+    fn test_play(mods: i32) -> ReworkPlay {
+        ReworkPlay {
+            mods,
+            max_combo: 50,
+            count_300: 55,
+            count_100: 3,
+            count_50: 1,
+            count_misses: 1,
+        }
+    }
+
+    fn round_trip(rework_id: i32, attributes: StoredOsuAttributes) -> StoredOsuAttributes {
+        match rework_id {
+            26 => StoredOsuAttributes::from(&aim_accuracy_fix::osu::OsuDifficultyAttributes::from(
+                attributes,
+            )),
+            27 => StoredOsuAttributes::from(
+                &improved_miss_penalty_and_acc_rework::osu::OsuDifficultyAttributes::from(
+                    attributes,
+                ),
+            ),
+            28 => StoredOsuAttributes::from(
+                &everything_at_once::osu::OsuDifficultyAttributes::from(attributes),
+            ),
+            _ => unreachable!("rework {} has no stored attributes", rework_id),
+        }
+    }
+
+    #[test]
+    fn stored_attributes_round_trip_through_every_revision() {
+        let beatmap = test_beatmap();
+
+        for (rework_id, _) in STORED_ATTRIBUTE_REWORKS {
+            let attributes = rework_difficulty_attributes(rework_id, &beatmap, 72).unwrap();
+            assert!(attributes.stars > 0.0, "rework {}", rework_id);
+
+            let converted = round_trip(rework_id, attributes.clone());
+            assert_eq!(
+                serde_json::to_value(&converted).unwrap(),
+                serde_json::to_value(&attributes).unwrap(),
+                "rework {}",
+                rework_id
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn stored_attributes_give_the_same_pp_as_the_beatmap() {
+        let beatmap = test_beatmap();
+
+        for (rework_id, _) in STORED_ATTRIBUTE_REWORKS {
+            for mods in [0, 8 | 64, 16] {
+                let play = test_play(mods);
+                let attributes = rework_difficulty_attributes(rework_id, &beatmap, mods).unwrap();
+
+                // as they are read back from redis
+                let stored =
+                    serde_json::to_string(&StoredDifficultyAttributes::Osu(attributes)).unwrap();
+                let Ok(StoredDifficultyAttributes::Osu(attributes)) = serde_json::from_str(&stored)
+                else {
+                    panic!("rework {} attributes did not deserialize as osu", rework_id);
+                };
+
+                let from_attributes =
+                    calculate_rework_pp_from_attributes(rework_id, &play, attributes).unwrap();
+                let from_beatmap = calculate_rework_pp(rework_id, &play, &beatmap)
+                    .await
+                    .unwrap();
+
+                assert!(from_beatmap > 0.0, "rework {} mods {}", rework_id, mods);
+                // the json round trip can move the last bit of a float, which can tip
+                // the rounding to two decimals
+                assert!(
+                    (from_attributes - from_beatmap).abs() <= 0.01,
+                    "rework {} mods {}: {} from attributes, {} from the beatmap",
+                    rework_id,
+                    mods,
+                    from_attributes,
+                    from_beatmap
+                );
+            }
+        }
+    }
+}
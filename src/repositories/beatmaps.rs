@@ -0,0 +1,25 @@
+use crate::context::Context;
+use crate::models::beatmap::Beatmap;
+use std::ops::DerefMut;
+use std::sync::Arc;
+
+pub struct BeatmapsRepository {
+    context: Arc<Context>,
+}
+
+impl BeatmapsRepository {
+    pub fn new(context: Arc<Context>) -> Self {
+        Self { context }
+    }
+
+    pub async fn fetch_by_md5(&self, beatmap_md5: &str) -> anyhow::Result<Option<Beatmap>> {
+        let beatmap: Option<Beatmap> = sqlx::query_as(
+            "SELECT beatmap_id, beatmapset_id, song_name FROM beatmaps WHERE beatmap_md5 = ?",
+        )
+        .bind(beatmap_md5)
+        .fetch_optional(self.context.database.get().await?.deref_mut())
+        .await?;
+
+        Ok(beatmap)
+    }
+}
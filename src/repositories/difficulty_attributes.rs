@@ -0,0 +1,52 @@
+use crate::context::Context;
+use crate::models::difficulty_attributes::{DifficultyAttributesKey, StoredDifficultyAttributes};
+use redis::AsyncCommands;
+use std::sync::Arc;
+
+pub struct DifficultyAttributesRepository {
+    context: Arc<Context>,
+}
+
+impl DifficultyAttributesRepository {
+    pub fn new(context: Arc<Context>) -> Self {
+        Self { context }
+    }
+
+    pub async fn fetch(
+        &self,
+        key: &DifficultyAttributesKey,
+    ) -> anyhow::Result<Option<StoredDifficultyAttributes>> {
+        let mut connection = self
+            .context
+            .redis
+            .get_multiplexed_async_connection()
+            .await?;
+        let attributes: Option<String> = connection.get(key.redis_key()).await?;
+
+        match attributes {
+            Some(attributes) => Ok(Some(serde_json::from_str(&attributes)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn store(
+        &self,
+        key: &DifficultyAttributesKey,
+        attributes: &StoredDifficultyAttributes,
+    ) -> anyhow::Result<()> {
+        let mut connection = self
+            .context
+            .redis
+            .get_multiplexed_async_connection()
+            .await?;
+        let _: () = connection
+            .set_ex(
+                key.redis_key(),
+                serde_json::to_string(attributes)?,
+                self.context.config.difficulty_attributes_ttl_secs,
+            )
+            .await?;
+
+        Ok(())
+    }
+}
@@ -1,3 +1,6 @@
+pub mod beatmaps;
+pub mod difficulty_attributes;
 pub mod leaderboards;
 pub mod reworks;
+pub mod scores;
 pub mod sessions;
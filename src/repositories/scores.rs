@@ -0,0 +1,38 @@
+use crate::context::Context;
+use crate::models::score::RippleScore;
+use std::ops::DerefMut;
+use std::sync::Arc;
+
+pub struct ScoresRepository {
+    context: Arc<Context>,
+}
+
+impl ScoresRepository {
+    pub fn new(context: Arc<Context>) -> Self {
+        Self { context }
+    }
+
+    pub async fn fetch_one(
+        &self,
+        scores_table: &str,
+        score_id: i64,
+    ) -> anyhow::Result<Option<RippleScore>> {
+        let score: Option<RippleScore> = sqlx::query_as(&format!(
+            "SELECT s.id, s.beatmap_md5, s.userid, s.score, s.max_combo, s.full_combo, s.mods, s.300_count,
+            s.100_count, s.50_count, s.katus_count, s.gekis_count, s.misses_count, s.time, s.play_mode, s.completed,
+            s.accuracy, s.pp, s.checksum, s.patcher, s.pinned, b.beatmap_id, b.beatmapset_id, b.song_name
+            FROM {} s
+            INNER JOIN
+                beatmaps b
+                USING(beatmap_md5)
+            WHERE
+                s.id = ?",
+            scores_table
+        ))
+        .bind(score_id)
+        .fetch_optional(self.context.database.get().await?.deref_mut())
+        .await?;
+
+        Ok(score)
+    }
+}
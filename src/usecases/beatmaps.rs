@@ -1,16 +1,322 @@
+use std::fmt;
+use std::future::Future;
 use std::sync::Arc;
+use std::time::Duration;
 
-use crate::context::Context;
+use akatsuki_pp_rs::Beatmap;
+use anyhow::Context as _;
+use rand::Rng;
 
+use crate::{
+    context::Context,
+    models::{self, beatmap_cache::CachedBeatmap, beatmap_source, mode::Mode},
+    repositories,
+};
+
+/// The beatmaps service returned a different version of the map than the one
+/// the caller expected, e.g. because it has been updated since the score was set.
+#[derive(Debug)]
+pub struct BeatmapMd5Mismatch {
+    pub beatmap_id: i32,
+    pub expected_md5: String,
+    pub actual_md5: String,
+}
+
+impl fmt::Display for BeatmapMd5Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "beatmap {} has md5 {}, expected {}",
+            self.beatmap_id, self.actual_md5, self.expected_md5
+        )
+    }
+}
+
+impl std::error::Error for BeatmapMd5Mismatch {}
+
+/// Only std beatmaps can be converted, a taiko map has no catch or mania version.
+#[derive(Debug)]
+pub struct IncompatibleConvert {
+    pub beatmap_mode: Mode,
+    pub mode: Mode,
+}
+
+impl fmt::Display for IncompatibleConvert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} beatmaps cannot be converted to {}, only std beatmaps can",
+            self.beatmap_mode.name(),
+            self.mode.name()
+        )
+    }
+}
+
+impl std::error::Error for IncompatibleConvert {}
+
+/// Checks that `beatmap` can be played in `mode`, returning whether it would be a convert.
+pub fn check_convert(beatmap: &Beatmap, mode: Mode) -> Result<bool, IncompatibleConvert> {
+    let beatmap_mode = Mode::from(beatmap.mode);
+    if beatmap_mode == mode {
+        return Ok(false);
+    }
+
+    if beatmap_mode != Mode::Std {
+        return Err(IncompatibleConvert { beatmap_mode, mode });
+    }
+
+    Ok(true)
+}
+
+/// The .osu file was fetched, but could not be parsed.
+#[derive(Debug)]
+pub struct UnparseableBeatmap {
+    pub beatmap_id: i32,
+    pub reason: String,
+}
+
+impl fmt::Display for UnparseableBeatmap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "failed to parse beatmap {}: {}",
+            self.beatmap_id, self.reason
+        )
+    }
+}
+
+impl std::error::Error for UnparseableBeatmap {}
+
+/// The beatmap source keeps failing, so fetches are refused until its circuit breaker
+/// lets them through again.
+#[derive(Debug)]
+pub struct BeatmapSourceUnavailable {
+    pub source: &'static str,
+    pub retry_in: Duration,
+}
+
+impl fmt::Display for BeatmapSourceUnavailable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the {} beatmap source is unavailable, retrying in {}s",
+            self.source,
+            self.retry_in.as_secs()
+        )
+    }
+}
+
+impl std::error::Error for BeatmapSourceUnavailable {}
+
+pub fn is_source_unavailable(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<BeatmapSourceUnavailable>().is_some()
+}
+
+/// Whether the source does not have the beatmap at all, as opposed to failing to serve it.
+pub fn is_beatmap_missing(error: &anyhow::Error) -> bool {
+    let not_found_status = error
+        .downcast_ref::<reqwest::Error>()
+        .and_then(|e| e.status())
+        .map_or(false, |status| status == reqwest::StatusCode::NOT_FOUND);
+    let missing_file = error
+        .downcast_ref::<std::io::Error>()
+        .map_or(false, |e| e.kind() == std::io::ErrorKind::NotFound);
+
+    not_found_status || missing_file
+}
+
+/// For background work that should pause rather than skip beatmaps during an outage.
+pub async fn wait_for_beatmap_source(context: &Context) {
+    if let Some(retry_in) = context.beatmap_source_breaker.open_for() {
+        log::warn!(
+            beatmap_source = context.beatmap_source.name(),
+            retry_in_secs = retry_in.as_secs();
+            "Beatmap source is unavailable, pausing until it recovers",
+        );
+    }
+
+    context.beatmap_source_breaker.wait_until_closed().await;
+}
+
+/// Runs `attempt` until it fails for another reason than the beatmap source being
+/// unavailable, waiting for the source to recover before each try. After
+/// BEATMAP_SOURCE_UNAVAILABLE_RETRIES outages the `BeatmapSourceUnavailable` error is
+/// returned, so a long outage does not hold the work forever.
+pub async fn retry_while_source_unavailable<T, F, Fut>(
+    context: &Context,
+    mut attempt: F,
+) -> anyhow::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = anyhow::Result<T>>,
+{
+    let mut retries = 0;
+
+    loop {
+        wait_for_beatmap_source(context).await;
+
+        match attempt().await {
+            Err(e)
+                if is_source_unavailable(&e)
+                    && retries < context.config.beatmap_source_unavailable_retries =>
+            {
+                retries += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Full jitter exponential backoff, capped by BEATMAP_FETCH_MAX_BACKOFF_MS.
+fn retry_backoff(attempt: u32, context: &Context) -> Duration {
+    let max_backoff_ms = context
+        .config
+        .beatmap_fetch_backoff_ms
+        .saturating_mul(1 << attempt.min(16))
+        .min(context.config.beatmap_fetch_max_backoff_ms);
+
+    Duration::from_millis(rand::thread_rng().gen_range(0..=max_backoff_ms))
+}
+
+pub fn beatmap_md5(beatmap_bytes: &[u8]) -> String {
+    format!("{:x}", md5::compute(beatmap_bytes))
+}
+
+pub fn verify_beatmap_md5(
+    beatmap_id: i32,
+    actual_md5: &str,
+    expected_md5: &str,
+) -> Result<(), BeatmapMd5Mismatch> {
+    if actual_md5.eq_ignore_ascii_case(expected_md5) {
+        return Ok(());
+    }
+
+    Err(BeatmapMd5Mismatch {
+        beatmap_id,
+        expected_md5: expected_md5.to_owned(),
+        actual_md5: actual_md5.to_owned(),
+    })
+}
+
+/// Fetches from the configured source, retrying errors that can be temporary. Failures
+/// that outlast the retries count towards the source's circuit breaker, and while it is
+/// open fetches fail straight away with `BeatmapSourceUnavailable`.
 pub async fn fetch_beatmap_osu_file(
     beatmap_id: i32,
     context: Arc<Context>,
 ) -> anyhow::Result<Vec<u8>> {
-    let base_url = &context.config.beatmaps_service_base_url;
-    let url = &format!("{base_url}/api/osu-api/v1/osu-files/{beatmap_id}");
-    let osu_response = reqwest::get(url).await?.error_for_status()?;
+    let source = context.beatmap_source.name();
+    let breaker = &context.beatmap_source_breaker;
+    if let Err(retry_in) = breaker.try_acquire() {
+        return Err(BeatmapSourceUnavailable { source, retry_in }.into());
+    }
+
+    let mut attempt = 0;
+    let error = loop {
+        let error = match context.beatmap_source.fetch_osu_file(beatmap_id).await {
+            Ok(bytes) => {
+                breaker.record_success();
+                return Ok(bytes);
+            }
+            Err(e) if beatmap_source::is_retryable(&e) => e,
+            Err(e) => {
+                // the source answered, so it is up
+                breaker.record_success();
+                return Err(e.context(format!(
+                    "failed to fetch beatmap {} from the {} beatmap source",
+                    beatmap_id, source
+                )));
+            }
+        };
+
+        if attempt >= context.config.beatmap_fetch_retries {
+            break error;
+        }
+
+        let backoff = retry_backoff(attempt, &context);
+        log::warn!(
+            beatmap_id = beatmap_id,
+            beatmap_source = source,
+            attempt = attempt + 1,
+            backoff_ms = backoff.as_millis() as u64,
+            error = error.to_string();
+            "Fetching beatmap failed, retrying",
+        );
+
+        tokio::time::sleep(backoff).await;
+        attempt += 1;
+    };
+
+    if breaker.record_failure() {
+        let retry_in = breaker.open_for().unwrap_or_default();
+        return Err(
+            anyhow::Error::new(BeatmapSourceUnavailable { source, retry_in })
+                .context(format!("{:#}", error)),
+        );
+    }
+
+    Err(error.context(format!(
+        "failed to fetch beatmap {} from the {} beatmap source after {} attempts",
+        beatmap_id,
+        source,
+        attempt + 1
+    )))
+}
 
-    let response_bytes = osu_response.bytes().await?.to_vec();
+/// Fetches the current version of a beatmap, bypassing the cache, and caches it.
+pub async fn refresh_beatmap(
+    beatmap_id: i32,
+    context: Arc<Context>,
+) -> anyhow::Result<Arc<CachedBeatmap>> {
+    let bytes = fetch_beatmap_osu_file(beatmap_id, context.clone()).await?;
+    let beatmap = Beatmap::from_bytes(&bytes).map_err(|e| UnparseableBeatmap {
+        beatmap_id,
+        reason: e.to_string(),
+    })?;
+
+    Ok(context.beatmap_cache.insert(CachedBeatmap {
+        beatmap_id,
+        md5: beatmap_md5(&bytes),
+        bytes,
+        beatmap,
+    }))
+}
+
+/// The cached version of a beatmap if there is one, which can be outdated if the map
+/// was updated since. Use `fetch_verified_beatmap` when the expected md5 is known.
+pub async fn fetch_beatmap(
+    beatmap_id: i32,
+    context: Arc<Context>,
+) -> anyhow::Result<Arc<CachedBeatmap>> {
+    if let Some(beatmap) = context.beatmap_cache.get_by_id(beatmap_id) {
+        return Ok(beatmap);
+    }
+
+    refresh_beatmap(beatmap_id, context).await
+}
+
+/// Fetches the beatmap and refuses it unless it hashes to `expected_md5`.
+pub async fn fetch_verified_beatmap(
+    beatmap_id: i32,
+    expected_md5: &str,
+    context: Arc<Context>,
+) -> anyhow::Result<Arc<CachedBeatmap>> {
+    if let Some(beatmap) = context.beatmap_cache.get(expected_md5) {
+        return Ok(beatmap);
+    }
+
+    let beatmap = refresh_beatmap(beatmap_id, context).await?;
+    verify_beatmap_md5(beatmap_id, &beatmap.md5, expected_md5)?;
+
+    Ok(beatmap)
+}
+
+pub async fn fetch_by_md5(
+    beatmap_md5: &str,
+    context: Arc<Context>,
+) -> anyhow::Result<Option<models::beatmap::Beatmap>> {
+    let repo = repositories::beatmaps::BeatmapsRepository::new(context);
+    let beatmap = repo.fetch_by_md5(beatmap_md5).await?;
 
-    Ok(response_bytes)
+    Ok(beatmap)
 }
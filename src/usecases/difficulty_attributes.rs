@@ -0,0 +1,54 @@
+use std::sync::Arc;
+
+use crate::{
+    context::Context,
+    models::difficulty_attributes::{DifficultyAttributesKey, StoredDifficultyAttributes},
+    repositories::difficulty_attributes::DifficultyAttributesRepository,
+};
+
+fn enabled(context: &Context) -> bool {
+    context.config.difficulty_attributes_ttl_secs > 0
+}
+
+/// Attributes stored by an earlier calculation. The store only saves work, so failing
+/// to read it is logged and treated as a miss.
+pub async fn fetch(
+    key: &DifficultyAttributesKey,
+    context: Arc<Context>,
+) -> Option<StoredDifficultyAttributes> {
+    if !enabled(&context) {
+        return None;
+    }
+
+    let repo = DifficultyAttributesRepository::new(context);
+    match repo.fetch(key).await {
+        Ok(attributes) => attributes,
+        Err(e) => {
+            log::warn!(
+                key = key.redis_key(),
+                error = e.to_string();
+                "Failed to read stored difficulty attributes",
+            );
+            None
+        }
+    }
+}
+
+pub async fn store(
+    key: &DifficultyAttributesKey,
+    attributes: &StoredDifficultyAttributes,
+    context: Arc<Context>,
+) {
+    if !enabled(&context) {
+        return;
+    }
+
+    let repo = DifficultyAttributesRepository::new(context);
+    if let Err(e) = repo.store(key, attributes).await {
+        log::warn!(
+            key = key.redis_key(),
+            error = e.to_string();
+            "Failed to store difficulty attributes",
+        );
+    }
+}
@@ -1,4 +1,7 @@
 pub mod beatmaps;
+pub mod difficulty_attributes;
 pub mod leaderboards;
+pub mod performance;
 pub mod reworks;
+pub mod scores;
 pub mod sessions;
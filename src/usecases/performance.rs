@@ -0,0 +1,30 @@
+use akatsuki_pp_rs::osu::OsuPerformanceAttributes;
+
+// the exponent osu!'s performance calculator sums the skill values with
+const SKILL_SUM_EXPONENT: f64 = 1.1;
+
+/// Autopilot moves the cursor for the player, so the cursor-driven skills (aim and
+/// flashlight) are dropped and the tapping & accuracy values are recombined with the
+/// multiplier the osu! calculator applied to the full sum.
+pub fn autopilot_pp(result: &OsuPerformanceAttributes) -> f64 {
+    let skill_sum = |values: &[f64]| {
+        values
+            .iter()
+            .map(|value| value.powf(SKILL_SUM_EXPONENT))
+            .sum::<f64>()
+            .powf(1.0 / SKILL_SUM_EXPONENT)
+    };
+
+    let full_sum = skill_sum(&[
+        result.pp_aim,
+        result.pp_speed,
+        result.pp_acc,
+        result.pp_flashlight,
+    ]);
+    if full_sum <= 0.0 {
+        return 0.0;
+    }
+
+    let multiplier = result.pp / full_sum;
+    skill_sum(&[result.pp_speed, result.pp_acc]) * multiplier
+}
@@ -0,0 +1,13 @@
+use crate::{context::Context, models::score::RippleScore, repositories};
+use std::sync::Arc;
+
+pub async fn fetch_one(
+    scores_table: &str,
+    score_id: i64,
+    context: Arc<Context>,
+) -> anyhow::Result<Option<RippleScore>> {
+    let repo = repositories::scores::ScoresRepository::new(context);
+    let score = repo.fetch_one(scores_table, score_id).await?;
+
+    Ok(score)
+}
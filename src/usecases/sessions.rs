@@ -1,4 +1,5 @@
 use crate::context::Context;
+use crate::models::privileges::USER_PUBLIC;
 use crate::models::queue::QueueRequest;
 use crate::models::queue::QueueResponse;
 use crate::models::rework::Rework;
@@ -62,24 +63,31 @@ pub async fn delete(session_token: String, context: Arc<Context>) -> anyhow::Res
     Ok(())
 }
 
-pub async fn enqueue(
-    session_token: String,
-    rework_id: i32,
-    context: Arc<Context>,
-) -> anyhow::Result<QueueResponse> {
+/// What a session is allowed to do.
+pub enum SessionAccess {
+    Granted {
+        user_id: i32,
+    },
+    /// The token does not belong to a session, e.g. because it expired.
+    InvalidSession,
+    /// The session is valid, but its user may not do this.
+    Denied(&'static str),
+}
+
+/// Checks that a session belongs to an unrestricted user with all of `privileges`.
+pub async fn authorize(
+    session_token: &str,
+    privileges: i32,
+    context: &Context,
+) -> anyhow::Result<SessionAccess> {
     let mut redis_conn = context.redis.get_multiplexed_async_connection().await?;
     let user_id: Option<i32> = redis_conn
         .get(format!("rework:sessions:{}", session_token))
         .await?;
 
-    if user_id.is_none() {
-        return Ok(QueueResponse {
-            success: false,
-            message: Some("Invalid session token".to_string()),
-        });
-    }
-
-    let user_id = user_id.unwrap();
+    let Some(user_id) = user_id else {
+        return Ok(SessionAccess::InvalidSession);
+    };
 
     let user_privileges: Option<(i32,)> =
         sqlx::query_as(r#"SELECT privileges FROM users WHERE id = ?"#)
@@ -87,20 +95,42 @@ pub async fn enqueue(
             .fetch_optional(context.database.get().await?.deref_mut())
             .await?;
 
-    if user_privileges.is_none() {
-        return Ok(QueueResponse {
-            success: false,
-            message: Some("User does not exist".to_string()),
-        });
+    let Some((user_privileges,)) = user_privileges else {
+        return Ok(SessionAccess::Denied("User does not exist"));
+    };
+
+    if user_privileges & USER_PUBLIC == 0 {
+        return Ok(SessionAccess::Denied("User is restricted"));
     }
 
-    if user_privileges.unwrap().0 & 1 == 0 {
-        return Ok(QueueResponse {
-            success: false,
-            message: Some("User is restricted".to_string()),
-        });
+    if user_privileges & privileges != privileges {
+        return Ok(SessionAccess::Denied("User is missing privileges"));
     }
 
+    Ok(SessionAccess::Granted { user_id })
+}
+
+pub async fn enqueue(
+    session_token: String,
+    rework_id: i32,
+    context: Arc<Context>,
+) -> anyhow::Result<QueueResponse> {
+    let user_id = match authorize(&session_token, USER_PUBLIC, &context).await? {
+        SessionAccess::Granted { user_id } => user_id,
+        SessionAccess::InvalidSession => {
+            return Ok(QueueResponse {
+                success: false,
+                message: Some("Invalid session token".to_string()),
+            });
+        }
+        SessionAccess::Denied(message) => {
+            return Ok(QueueResponse {
+                success: false,
+                message: Some(message.to_string()),
+            });
+        }
+    };
+
     let rework: Rework = sqlx::query_as(r#"SELECT * FROM reworks WHERE rework_id = ?"#)
         .bind(rework_id)
         .fetch_one(context.database.get().await?.deref_mut())